@@ -0,0 +1,365 @@
+use crate::html_text;
+use crate::linkable_text;
+
+use failure::ResultExt;
+use regex::{Captures, Regex};
+use std::collections::HashSet;
+
+const ASN1_START: &str = "-- ASN1START";
+const ASN1_STOP: &str = "-- ASN1STOP";
+const ASN1_NAME: &str = r#"[A-Za-z][A-Za-z0-9]*(?:-[A-Za-z0-9]+)*"#;
+
+// An ASN1START to ASN1STOP block: where its paragraphs are in the document and their lines
+struct Asn1Block {
+    start: usize,
+    end: usize,
+    lines: Vec<String>,
+}
+
+// Replaces the PL paragraphs of every ASN.1 block by a <pre> block with its lines as they are.
+// Type and value definitions get an anchor, names defined in any block of the document link to
+// their definition.
+pub fn render_asn1_blocks(content: &str) -> String {
+    println!("\tRendering ASN.1 blocks...");
+
+    let blocks = find_asn1_blocks(content);
+    if blocks.is_empty() {
+        return String::from(content);
+    }
+
+    let definition_re = definition_regex();
+    let name_re = Regex::new(&format!(r#"\b{}"#, ASN1_NAME)).unwrap();
+
+    let mut definitions = HashSet::new();
+    for block in &blocks {
+        for line in &block.lines {
+            if let Some(name) = defined_name(line, &definition_re) {
+                definitions.insert(name);
+            }
+        }
+    }
+
+    let mut result = String::new();
+    let mut anchored = HashSet::new();
+    let mut copied_until = 0;
+    for block in &blocks {
+        result.push_str(&content[copied_until..block.start]);
+        result.push_str("<pre class=\"asn1\">");
+        for (i, line) in block.lines.iter().enumerate() {
+            if i > 0 {
+                result.push('\n');
+            }
+            result.push_str(&render_line(
+                line,
+                &definition_re,
+                &name_re,
+                &definitions,
+                &mut anchored,
+            ));
+        }
+        result.push_str("</pre>");
+        copied_until = block.end;
+    }
+    result.push_str(&content[copied_until..]);
+
+    result
+}
+
+fn find_asn1_blocks(content: &str) -> Vec<Asn1Block> {
+    let paragraph_re = Regex::new(r#"(?is)<p(\s[^>]*)?>(?P<content>.*?)</p>"#).unwrap();
+
+    let mut blocks = Vec::new();
+    let mut current: Option<Asn1Block> = None;
+    for cap in paragraph_re.captures_iter(content) {
+        let paragraph = cap.get(0).unwrap();
+        let line = html_text::program_listing_text(&cap["content"]);
+
+        if let Some(block) = &mut current {
+            block.lines.push(line.clone());
+            if line.trim() == ASN1_STOP {
+                block.end = paragraph.end();
+                blocks.push(current.take().unwrap());
+            }
+        } else if line.trim() == ASN1_START {
+            current = Some(Asn1Block {
+                start: paragraph.start(),
+                end: paragraph.end(),
+                lines: vec![line],
+            });
+        }
+    }
+
+    blocks
+}
+
+// "Foo ::= SEQUENCE {", "Foo {Bar} ::=" and "maxFoo INTEGER ::= 8" define Foo and maxFoo
+fn definition_regex() -> Regex {
+    Regex::new(&format!(
+        r#"^(?P<type>[A-Z]{0})\s*(\{{[^}}]*\}}\s*)?::=|^(?P<value>[a-z]{0})\s+[A-Z][A-Za-z0-9-]*(\s*\([^)]*\))?\s*::="#,
+        r#"[A-Za-z0-9]*(?:-[A-Za-z0-9]+)*"#
+    ))
+    .unwrap()
+}
+
+fn defined_name(line: &str, definition_re: &Regex) -> Option<String> {
+    let cap = definition_re.captures(line)?;
+    cap.name("type")
+        .or_else(|| cap.name("value"))
+        .map(|name| String::from(name.as_str()))
+}
+
+// The comment of a line is copied as it is, names in the code before it are linked
+fn render_line(
+    line: &str,
+    definition_re: &Regex,
+    name_re: &Regex,
+    definitions: &HashSet<String>,
+    anchored: &mut HashSet<String>,
+) -> String {
+    let (code, comment) = match line.find("--") {
+        Some(position) => line.split_at(position),
+        None => (line, ""),
+    };
+    let definition = defined_name(line, definition_re);
+
+    let mut result = String::new();
+    let mut copied_until = 0;
+    for name in name_re.find_iter(code) {
+        result.push_str(&html_text::escape(&code[copied_until..name.start()]));
+        let is_definition = name.start() == 0 && definition.as_deref() == Some(name.as_str());
+
+        if is_definition && anchored.insert(String::from(name.as_str())) {
+            result.push_str(&format!("<a id=\"asn1-{0}\">{0}</a>", name.as_str()));
+        } else if !is_definition && definitions.contains(name.as_str()) {
+            result.push_str(&format!("<a href=\"#asn1-{0}\">{0}</a>", name.as_str()));
+        } else {
+            result.push_str(name.as_str());
+        }
+        copied_until = name.end();
+    }
+    result.push_str(&html_text::escape(&code[copied_until..]));
+    result.push_str(&html_text::escape(comment));
+
+    result
+}
+
+// Links names of ASN.1 types in the text to their definition. Only names that cannot be
+// ordinary words are linked: "RRCReconfiguration", "SIB1" or "PDU-Session", not "Cause".
+pub fn add_asn1_type_links(content: &str) -> String {
+    println!("\tAdding ASN.1 type links...");
+
+    let anchor_re = Regex::new(r#"<a id="asn1-(?P<name>[^"]+)">"#).unwrap();
+    let definitions = anchor_re
+        .captures_iter(content)
+        .map(|cap| String::from(&cap["name"]))
+        .filter(|name| is_distinctive_name(name))
+        .collect::<HashSet<_>>();
+    if definitions.is_empty() {
+        return String::from(content);
+    }
+
+    let name_re = Regex::new(&format!(r#"\b{}"#, ASN1_NAME)).unwrap();
+    linkable_text::replace_linkable_text(content, |text, _| {
+        String::from(name_re.replace_all(text, |cap: &Captures| {
+            let name = &cap[0];
+            if definitions.contains(name) {
+                format!("<a href=\"#asn1-{0}\">{0}</a>", name)
+            } else {
+                String::from(name)
+            }
+        }))
+    })
+}
+
+fn is_distinctive_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name
+            .chars()
+            .skip(1)
+            .any(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '-')
+}
+
+// Writes the ASN.1 modules of a document to <module name>.asn files. Blocks outside of a module
+// definition go to <ts_no>.asn.
+pub fn save_asn1_modules(
+    output_dir: &str,
+    ts_no: &str,
+    content: &str,
+) -> Result<(), failure::Error> {
+    let modules = extract_asn1_modules(content, ts_no);
+    if modules.is_empty() {
+        return Ok(());
+    }
+
+    println!("\tSaving ASN.1 modules...");
+    for (name, text) in modules {
+        let path = format!("{}/{}.asn", output_dir, name);
+        std::fs::write(&path, text)
+            .with_context(|_| format!("could not write ASN.1 file `{}`", path))?;
+    }
+
+    Ok(())
+}
+
+fn extract_asn1_modules(content: &str, ts_no: &str) -> Vec<(String, String)> {
+    let pre_re = Regex::new(r#"(?s)<pre class="asn1">(?P<code>.*?)</pre>"#).unwrap();
+    let tag_re = Regex::new(r#"(?s)<[^<>]+?>"#).unwrap();
+    let module_re = Regex::new(
+        r#"(?m)^(?P<name>[A-Z][A-Za-z0-9]*(?:-[A-Za-z0-9]+)*)\s*(\{[^}]*\})?\s*DEFINITIONS\b"#,
+    )
+    .unwrap();
+    let end_re = Regex::new(r#"(?m)^END[ \t]*$"#).unwrap();
+
+    // The blocks of a module are split by the text of the specification between them
+    let code = pre_re
+        .captures_iter(content)
+        .map(|cap| html_text::unescape(&tag_re.replace_all(&cap["code"], "")))
+        .flat_map(|block| {
+            block
+                .lines()
+                .filter(|line| ![ASN1_START, ASN1_STOP].contains(&line.trim()))
+                .map(String::from)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut modules = Vec::new();
+    let mut outside = String::new();
+    let mut copied_until = 0;
+    for cap in module_re.captures_iter(&code) {
+        let start = cap.get(0).unwrap().start();
+        if start < copied_until {
+            continue;
+        }
+        outside.push_str(&code[copied_until..start]);
+
+        let end = end_re
+            .find_at(&code, start)
+            .map(|end| end.end())
+            .unwrap_or_else(|| code.len());
+        let mut text = String::from(&code[start..end]);
+        text.push('\n');
+        modules.push((String::from(&cap["name"]), text));
+        copied_until = end;
+    }
+    outside.push_str(&code[copied_until..]);
+
+    if !outside.trim().is_empty() {
+        let mut text = String::from(outside.trim_matches('\n'));
+        text.push('\n');
+        modules.push((String::from(ts_no), text));
+    }
+
+    modules
+}
+
+#[cfg(test)]
+const TEST_SOURCE: &str = r#"<p class="western">The <i>RRCSetup</i> message.</p>
+<p class="PL-western"><font face="Courier New">-- ASN1START</font></p>
+<p class="PL-western"><br/></p>
+<p class="PL-western">NR-RRC-Definitions DEFINITIONS AUTOMATIC TAGS ::=</p>
+<p class="PL-western">BEGIN</p>
+<p class="PL-western">RRCSetup ::= SEQUENCE {</p>
+<p class="PL-western">	criticalExtensions	RRCSetup-IEs	-- RRCSetup-IEs &amp; more</p>
+<p class="PL-western">}</p>
+<p class="PL-western">-- ASN1STOP</p>
+<p class="western">Text</p>
+<p class="PL-western">-- ASN1START</p>
+<p class="PL-western">RRCSetup-IEs ::= SEQUENCE { maxFoo INTEGER }</p>
+<p class="PL-western">maxFoo INTEGER ::= 8</p>
+<p class="PL-western">END</p>
+<p class="PL-western">-- ASN1STOP</p>"#;
+
+#[test]
+fn test_render_asn1_blocks() {
+    let expected = r##"<p class="western">The <i>RRCSetup</i> message.</p>
+<pre class="asn1">-- ASN1START
+
+NR-RRC-Definitions DEFINITIONS AUTOMATIC TAGS ::=
+BEGIN
+<a id="asn1-RRCSetup">RRCSetup</a> ::= SEQUENCE {
+	criticalExtensions	<a href="#asn1-RRCSetup-IEs">RRCSetup-IEs</a>	-- RRCSetup-IEs &amp; more
+}
+-- ASN1STOP</pre>
+<p class="western">Text</p>
+<pre class="asn1">-- ASN1START
+<a id="asn1-RRCSetup-IEs">RRCSetup-IEs</a> ::= SEQUENCE { <a href="#asn1-maxFoo">maxFoo</a> INTEGER }
+<a id="asn1-maxFoo">maxFoo</a> INTEGER ::= 8
+END
+-- ASN1STOP</pre>"##;
+
+    assert_eq!(render_asn1_blocks(TEST_SOURCE), expected);
+}
+
+#[test]
+fn test_add_asn1_type_links() {
+    let source = render_asn1_blocks(TEST_SOURCE);
+    let result = add_asn1_type_links(&source);
+
+    assert!(result.starts_with(
+        r##"<p class="western">The <i><a href="#asn1-RRCSetup">RRCSetup</a></i> message.</p>"##
+    ));
+    // The blocks are not changed, "maxFoo" is not linked in the text
+    assert_eq!(
+        result[result.find("<pre").unwrap()..],
+        source[source.find("<pre").unwrap()..]
+    );
+}
+
+#[test]
+fn test_extract_asn1_modules() {
+    let content = format!(
+        "{}<p class=\"PL-western\">-- ASN1START</p><p class=\"PL-western\">Foo ::= INTEGER</p><p class=\"PL-western\">-- ASN1STOP</p>",
+        TEST_SOURCE
+    );
+    let modules = extract_asn1_modules(&render_asn1_blocks(&content), "38.331");
+
+    let expected_module = "NR-RRC-Definitions DEFINITIONS AUTOMATIC TAGS ::=
+BEGIN
+RRCSetup ::= SEQUENCE {
+	criticalExtensions	RRCSetup-IEs	-- RRCSetup-IEs & more
+}
+RRCSetup-IEs ::= SEQUENCE { maxFoo INTEGER }
+maxFoo INTEGER ::= 8
+END
+";
+    assert_eq!(
+        modules,
+        vec![
+            (
+                String::from("NR-RRC-Definitions"),
+                String::from(expected_module)
+            ),
+            (String::from("38.331"), String::from("Foo ::= INTEGER\n")),
+        ]
+    );
+}
+
+#[test]
+fn test_defined_name() {
+    let definition_re = definition_regex();
+
+    assert_eq!(
+        defined_name("Foo ::= SEQUENCE {", &definition_re).unwrap(),
+        "Foo"
+    );
+    assert_eq!(
+        defined_name("Foo-Bar{ IEsSetParam } ::=", &definition_re).unwrap(),
+        "Foo-Bar"
+    );
+    assert_eq!(
+        defined_name("id-AMFName ProtocolIE-ID ::= 1", &definition_re).unwrap(),
+        "id-AMFName"
+    );
+    assert_eq!(
+        defined_name("maxFoo INTEGER ::= 8", &definition_re).unwrap(),
+        "maxFoo"
+    );
+    assert!(defined_name("\tfoo\tFoo,", &definition_re).is_none());
+    assert!(defined_name(
+        "NR-RRC-Definitions DEFINITIONS AUTOMATIC TAGS ::=",
+        &definition_re
+    )
+    .is_none());
+}
@@ -0,0 +1,176 @@
+use crate::html_tokens;
+use crate::references;
+use crate::references::DocumentReferences;
+
+use failure::ResultExt;
+use regex::{Captures, Regex};
+use std::collections::{BTreeSet, HashMap};
+
+// Citing TS number and clause of every referenced clause, keyed by TS number and clause
+type BacklinkIndex = HashMap<String, HashMap<String, BTreeSet<(String, Option<String>)>>>;
+
+// Puts a "Referenced from" list after every clause heading of the documents in the output
+// directory. Lists from the previous run are replaced, so the documents can be updated many times.
+pub fn add_backlinks(out_path: &std::path::Path) -> Result<(), failure::Error> {
+    println!("Adding backlinks...");
+
+    let all_references = references::load_all_references(out_path)?;
+    let backlink_index = build_backlink_index(&all_references);
+    let no_backlinks = HashMap::new();
+
+    for document in &all_references {
+        let html_path = out_path
+            .join(&document.ts_no)
+            .join(format!("{}.html", document.ts_no));
+        if !html_path.is_file() {
+            continue;
+        }
+
+        println!("\t{}", html_path.display());
+        let content = std::fs::read_to_string(&html_path)
+            .with_context(|_| format!("could not read HTML file `{}`", html_path.display()))?;
+        let backlinks = backlink_index.get(&document.ts_no).unwrap_or(&no_backlinks);
+        let content = insert_backlinks(&content, &document.ts_no, backlinks);
+        std::fs::write(&html_path, content)
+            .with_context(|_| format!("could not write HTML file `{}`", html_path.display()))?;
+    }
+
+    Ok(())
+}
+
+fn build_backlink_index(all_references: &[DocumentReferences]) -> BacklinkIndex {
+    let mut backlink_index = BacklinkIndex::new();
+
+    for document in all_references {
+        for reference in &document.references {
+            // Links to a whole document have no clause to show them in
+            let target_clause = match &reference.target_clause {
+                Some(target_clause) => target_clause,
+                None => continue,
+            };
+            let is_self_reference = document.ts_no == reference.target_ts_no
+                && reference.source_clause.as_ref() == Some(target_clause);
+            if is_self_reference {
+                continue;
+            }
+
+            backlink_index
+                .entry(reference.target_ts_no.clone())
+                .or_default()
+                .entry(target_clause.clone())
+                .or_default()
+                .insert((document.ts_no.clone(), reference.source_clause.clone()));
+        }
+    }
+
+    backlink_index
+}
+
+fn insert_backlinks(
+    content: &str,
+    ts_no: &str,
+    backlinks: &HashMap<String, BTreeSet<(String, Option<String>)>>,
+) -> String {
+    let old_backlinks_re = Regex::new(r#"(?s)<details class="backlinks">.*?</details>"#).unwrap();
+    let content = old_backlinks_re.replace_all(content, "");
+
+    let heading_re = Regex::new(html_tokens::HEADING_WITH_ID_PATTERN).unwrap();
+    let result =
+        heading_re.replace_all(&content, |cap: &Captures| match backlinks.get(&cap["id"]) {
+            Some(sources) => format!("{}{}", &cap[0], render_backlinks(ts_no, sources)),
+            None => String::from(&cap[0]),
+        });
+
+    String::from(result)
+}
+
+fn render_backlinks(ts_no: &str, sources: &BTreeSet<(String, Option<String>)>) -> String {
+    let items = sources
+        .iter()
+        .map(|(source_ts_no, source_clause)| {
+            let document_link = if source_ts_no == ts_no {
+                String::new()
+            } else {
+                format!("../{0}/{0}.html", source_ts_no)
+            };
+
+            let (href, text) = match source_clause {
+                Some(clause) if source_ts_no == ts_no => {
+                    (format!("#{}", clause), format!("clause {}", clause))
+                }
+                Some(clause) => (
+                    format!("{}#{}", document_link, clause),
+                    format!("TS {} clause {}", source_ts_no, clause),
+                ),
+                None => (document_link, format!("TS {}", source_ts_no)),
+            };
+
+            format!("<li><a href=\"{}\">{}</a></li>", href, text)
+        })
+        .collect::<Vec<_>>();
+
+    format!(
+        "<details class=\"backlinks\"><summary>Referenced from {} {}</summary><ul>{}</ul></details>",
+        items.len(),
+        if items.len() == 1 { "place" } else { "places" },
+        items.join("")
+    )
+}
+
+#[cfg(test)]
+use crate::references::ClauseReference;
+
+#[cfg(test)]
+fn reference(
+    source_clause: Option<&str>,
+    target_ts_no: &str,
+    target_clause: &str,
+) -> ClauseReference {
+    ClauseReference {
+        source_clause: source_clause.map(String::from),
+        target_ts_no: String::from(target_ts_no),
+        target_clause: Some(String::from(target_clause)),
+    }
+}
+
+#[test]
+fn test_build_backlink_index_skips_self_references() {
+    let all_references = vec![
+        DocumentReferences {
+            ts_no: String::from("23.501"),
+            references: vec![
+                reference(Some("4.2"), "23.501", "4.2"),
+                reference(Some("4.2"), "23.501", "5.1"),
+            ],
+        },
+        DocumentReferences {
+            ts_no: String::from("23.502"),
+            references: vec![
+                reference(Some("4.3"), "23.501", "5.1"),
+                reference(Some("4.3"), "23.501", "5.1"),
+            ],
+        },
+    ];
+
+    let backlink_index = build_backlink_index(&all_references);
+
+    let sources = &backlink_index["23.501"]["5.1"];
+    assert_eq!(sources.len(), 2);
+    assert!(sources.contains(&(String::from("23.501"), Some(String::from("4.2")))));
+    assert!(sources.contains(&(String::from("23.502"), Some(String::from("4.3")))));
+    assert!(!backlink_index["23.501"].contains_key("4.2"));
+}
+
+#[test]
+fn test_insert_backlinks() {
+    let mut backlinks = HashMap::new();
+    let mut sources = BTreeSet::new();
+    sources.insert((String::from("23.501"), Some(String::from("4.2"))));
+    sources.insert((String::from("23.502"), Some(String::from("4.3"))));
+    backlinks.insert(String::from("5.1"), sources);
+
+    let source = r#"<h2 id="5.1" class="western">5.1 Foo</h2><details class="backlinks"><summary>Old</summary></details><p>Bar</p><h2 id="5.2">5.2 Baz</h2>"#;
+    let expected = r##"<h2 id="5.1" class="western">5.1 Foo</h2><details class="backlinks"><summary>Referenced from 2 places</summary><ul><li><a href="#4.2">clause 4.2</a></li><li><a href="../23.502/23.502.html#4.3">TS 23.502 clause 4.3</a></li></ul></details><p>Bar</p><h2 id="5.2">5.2 Baz</h2>"##;
+
+    assert_eq!(insert_backlinks(source, "23.501", &backlinks), expected);
+}
@@ -0,0 +1,417 @@
+use crate::html_text;
+use crate::html_tokens;
+
+use failure::ResultExt;
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+
+pub const CHUNKS_FILE_NAME: &str = "chunks.json";
+
+// File of every id of a chunked document, ids on the front page are left out
+type ChunkMap = HashMap<String, String>;
+
+// Part of a document starting with the heading of a clause, the front page has no clause
+struct Chunk<'a> {
+    clause: Option<String>,
+    title: String,
+    file_name: String,
+    body: &'a str,
+}
+
+// Splits the documents converted in this run into a page per clause of at most `depth` levels.
+// The first page keeps the name of the document and everything before the first of these
+// clauses. Links are rewritten to the page of their target, also links to documents chunked in
+// a previous run.
+pub fn split_documents(
+    out_path: &std::path::Path,
+    ts_nos: &[String],
+    depth: usize,
+) -> Result<(), failure::Error> {
+    println!("Splitting documents into chunks...");
+
+    let mut chunk_maps = load_chunk_maps(out_path)?;
+    let mut documents = Vec::new();
+
+    for ts_no in ts_nos {
+        let html_path = out_path.join(ts_no).join(format!("{}.html", ts_no));
+        let content = std::fs::read_to_string(&html_path)
+            .with_context(|_| format!("could not read HTML file `{}`", html_path.display()))?;
+
+        let chunk_map = {
+            let (_, chunks, _) = split_page(&content, ts_no, depth);
+            build_chunk_map(&chunks)
+        };
+        let map_path = out_path.join(ts_no).join(CHUNKS_FILE_NAME);
+        std::fs::write(&map_path, serde_json::to_string(&chunk_map)?)
+            .with_context(|_| format!("could not write chunk map `{}`", map_path.display()))?;
+
+        chunk_maps.insert(ts_no.clone(), chunk_map);
+        documents.push((ts_no, html_path, content));
+    }
+
+    for (ts_no, html_path, content) in &documents {
+        println!("\t{}", html_path.display());
+        let dir = html_path.parent().unwrap();
+        remove_chunk_files(dir, ts_no)?;
+
+        for (file_name, page) in render_chunks(content, ts_no, depth, &chunk_maps) {
+            let path = dir.join(&file_name);
+            std::fs::write(&path, page)
+                .with_context(|_| format!("could not write chunk `{}`", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+// Chunks of a previous run, another depth may have split the document differently
+fn remove_chunk_files(dir: &std::path::Path, ts_no: &str) -> Result<(), failure::Error> {
+    let prefix = format!("{}-", ts_no);
+
+    for entry in std::fs::read_dir(dir)
+        .with_context(|_| format!("could not read directory `{}`", dir.display()))?
+    {
+        let path = entry?.path();
+        let is_chunk = path.file_name().is_some_and(|name| {
+            let name = name.to_string_lossy();
+            name.starts_with(&prefix) && name.ends_with(".html")
+        });
+        if is_chunk {
+            std::fs::remove_file(&path)
+                .with_context(|_| format!("could not remove chunk `{}`", path.display()))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn load_chunk_maps(
+    out_path: &std::path::Path,
+) -> Result<HashMap<String, ChunkMap>, failure::Error> {
+    let mut chunk_maps = HashMap::new();
+
+    for entry in std::fs::read_dir(out_path)
+        .with_context(|_| format!("could not read output directory `{}`", out_path.display()))?
+    {
+        let dir = entry?.path();
+        let path = dir.join(CHUNKS_FILE_NAME);
+        if !path.is_file() {
+            continue;
+        }
+
+        let ts_no = dir.file_name().unwrap().to_string_lossy().to_string();
+        let content = std::fs::read_to_string(&path)
+            .with_context(|_| format!("could not read chunk map `{}`", path.display()))?;
+        let chunk_map = serde_json::from_str(&content)
+            .with_context(|_| format!("could not parse chunk map `{}`", path.display()))?;
+        chunk_maps.insert(ts_no, chunk_map);
+    }
+
+    Ok(chunk_maps)
+}
+
+// "4" has depth 1, "4.2.3" and "A.2.3" depth 3
+fn clause_depth(clause: &str) -> usize {
+    clause.split('.').count()
+}
+
+// "23.501-5.6.html" for clause 5.6
+fn chunk_file_name(ts_no: &str, clause: Option<&str>) -> String {
+    match clause {
+        Some(clause) => format!("{}-{}.html", ts_no, clause),
+        None => format!("{}.html", ts_no),
+    }
+}
+
+// The part of the page before the document body, the chunks of the body and the part after it
+fn split_page<'a>(
+    content: &'a str,
+    ts_no: &str,
+    depth: usize,
+) -> (&'a str, Vec<Chunk<'a>>, &'a str) {
+    // The document is in <main> of the template, or in <body> of a template without <main>
+    let main_re = Regex::new(r#"(?is)<main[^>]*>(?P<body>.*)</main>"#).unwrap();
+    let body_re = Regex::new(r#"(?is)<body[^>]*>(?P<body>.*)</body>"#).unwrap();
+    let heading_re = Regex::new(html_tokens::HEADING_WITH_ID_PATTERN).unwrap();
+
+    let (begin, end) = match main_re
+        .captures(content)
+        .or_else(|| body_re.captures(content))
+        .and_then(|cap| cap.name("body"))
+    {
+        Some(body) => (body.start(), body.end()),
+        None => (0, content.len()),
+    };
+    let body = &content[begin..end];
+
+    let mut chunks = Vec::new();
+    let mut chunk_begin = 0;
+    let mut clause = None;
+    let mut title = String::new();
+
+    for cap in heading_re.captures_iter(body) {
+        let id = &cap["id"];
+        if clause_depth(id) > depth {
+            continue;
+        }
+
+        let heading_begin = cap.get(0).unwrap().start();
+        chunks.push(Chunk {
+            file_name: chunk_file_name(ts_no, clause.as_deref()),
+            clause,
+            title,
+            body: &body[chunk_begin..heading_begin],
+        });
+
+        chunk_begin = heading_begin;
+        clause = Some(String::from(id));
+        title = html_text::to_plain_text(&cap["heading"]);
+    }
+
+    chunks.push(Chunk {
+        file_name: chunk_file_name(ts_no, clause.as_deref()),
+        clause,
+        title,
+        body: &body[chunk_begin..],
+    });
+
+    (&content[..begin], chunks, &content[end..])
+}
+
+fn build_chunk_map(chunks: &[Chunk]) -> ChunkMap {
+    let id_re = Regex::new(r#"\sid="(?P<id>[^"]+)""#).unwrap();
+    let mut chunk_map = ChunkMap::new();
+
+    for chunk in chunks.iter().filter(|chunk| chunk.clause.is_some()) {
+        for cap in id_re.captures_iter(chunk.body) {
+            chunk_map.insert(String::from(&cap["id"]), chunk.file_name.clone());
+        }
+    }
+
+    chunk_map
+}
+
+// Page of a chunk of a document chunked in this or a previous run. Ids unknown in a chunked
+// document are found by the clause they are in: "5.6.3" and "5.6-1" are in the chunk of "5.6".
+fn find_chunk(chunk_map: &ChunkMap, ts_no: &str, id: &str) -> String {
+    if let Some(file_name) = chunk_map.get(id) {
+        return file_name.clone();
+    }
+
+    let clause = id.trim_start_matches("table-");
+    let mut clause = clause.split('-').next().unwrap();
+    while let Some(last_dot) = clause.rfind('.') {
+        clause = &clause[..last_dot];
+        if let Some(file_name) = chunk_map.get(clause) {
+            return file_name.clone();
+        }
+    }
+
+    chunk_file_name(ts_no, None)
+}
+
+fn rewrite_links(
+    content: &str,
+    ts_no: &str,
+    file_name: &str,
+    chunk_maps: &HashMap<String, ChunkMap>,
+) -> String {
+    let link_re =
+        Regex::new(r##"href="(\.\./(?P<ts_no>[^/"]+)/(?P<file>[^/"#]+))?#(?P<id>[^"]+)""##)
+            .unwrap();
+
+    let result = link_re.replace_all(content, |cap: &Captures| {
+        let id = &cap["id"];
+        let target_ts_no = cap.name("ts_no").map_or(ts_no, |ts_no| ts_no.as_str());
+        if let Some(file) = cap.name("file") {
+            if file.as_str() != format!("{}.html", target_ts_no) {
+                return String::from(&cap[0]);
+            }
+        }
+
+        let chunk_map = match chunk_maps.get(target_ts_no) {
+            Some(chunk_map) => chunk_map,
+            None => return String::from(&cap[0]),
+        };
+        let target_file = find_chunk(chunk_map, target_ts_no, id);
+
+        if target_ts_no != ts_no {
+            format!("href=\"../{}/{}#{}\"", target_ts_no, target_file, id)
+        } else if target_file == file_name {
+            format!("href=\"#{}\"", id)
+        } else {
+            format!("href=\"{}#{}\"", target_file, id)
+        }
+    });
+
+    String::from(result)
+}
+
+fn render_chunk_nav(chunks: &[Chunk], index: usize) -> String {
+    let link = |chunk: &Chunk, text: &str| {
+        format!(
+            "<a href=\"{}\">{}</a>",
+            chunk.file_name,
+            html_text::escape(text)
+        )
+    };
+
+    let mut items = Vec::new();
+    if index > 0 {
+        let previous = &chunks[index - 1];
+        let title = if previous.clause.is_some() {
+            previous.title.as_str()
+        } else {
+            "Front page"
+        };
+        items.push(format!(
+            "<span class=\"chunk-prev\">&larr; {}</span>",
+            link(previous, title)
+        ));
+    }
+    if index > 0 {
+        items.push(link(&chunks[0], "Contents"));
+    }
+    if let Some(next) = chunks.get(index + 1) {
+        items.push(format!(
+            "<span class=\"chunk-next\">{} &rarr;</span>",
+            link(next, &next.title)
+        ));
+    }
+
+    format!("<nav class=\"chunk-nav\">{}</nav>\n", items.join(" | "))
+}
+
+// Sends links to ids of other chunks, e.g. from the search page, to the right page
+fn render_redirect_script(chunk_map: &ChunkMap) -> String {
+    format!(
+        r#"<script>
+(function () {{
+  var chunks = {};
+  function redirect() {{
+    var file = chunks[decodeURIComponent(location.hash.substring(1))];
+    if (file) {{
+      location.replace(file + location.hash);
+    }}
+  }}
+  redirect();
+  window.addEventListener("hashchange", redirect);
+}})();
+</script>
+"#,
+        serde_json::to_string(chunk_map).unwrap()
+    )
+}
+
+fn render_chunks(
+    content: &str,
+    ts_no: &str,
+    depth: usize,
+    chunk_maps: &HashMap<String, ChunkMap>,
+) -> Vec<(String, String)> {
+    let (before, chunks, after) = split_page(content, ts_no, depth);
+    let body_end_re = Regex::new(r#"(?i)</body>"#).unwrap();
+
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            let nav = render_chunk_nav(&chunks, i);
+            let mut after = String::from(after);
+            if i == 0 {
+                if let Some(chunk_map) = chunk_maps.get(ts_no) {
+                    let script = render_redirect_script(chunk_map);
+                    after = String::from(
+                        body_end_re.replace(&after, |_: &Captures| format!("{}</body>", script)),
+                    );
+                }
+            }
+
+            let page = format!("{}\n{}{}\n{}{}", before, nav, chunk.body.trim(), nav, after);
+            let page = rewrite_links(&page, ts_no, &chunk.file_name, chunk_maps);
+            (chunk.file_name.clone(), page)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+const TEST_PAGE: &str = r##"<html><body><nav><a href="#4.1">4.1</a></nav><main>
+<p>Cover</p><p><a href="#5">5</a></p>
+<h1 id="4" >4 Foo</h1><p>See <a href="#4.1">4.1</a> and <a href="../23.502/23.502.html#4.3.2">TS 23.502 clause 4.3.2</a>.</p>
+<h2 id="4.1" >4.1 Bar</h2><p><b id="table-4.1-1">Table 4.1-1: Baz</b></p>
+<h1 id="5" >5 Qux</h1><p>See <a href="#table-4.1-1">Table 4.1-1</a> and <a href="#5">5</a>.</p>
+</main></body></html>"##;
+
+#[test]
+fn test_split_page() {
+    let (before, chunks, after) = split_page(TEST_PAGE, "23.501", 1);
+
+    assert_eq!(
+        before,
+        "<html><body><nav><a href=\"#4.1\">4.1</a></nav><main>"
+    );
+    assert_eq!(after, "</main></body></html>");
+    assert_eq!(
+        chunks
+            .iter()
+            .map(|chunk| (chunk.file_name.as_str(), chunk.title.as_str()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("23.501.html", ""),
+            ("23.501-4.html", "4 Foo"),
+            ("23.501-5.html", "5 Qux")
+        ]
+    );
+    assert!(chunks[1].body.contains("<h2 id=\"4.1\" >"));
+
+    let chunk_map = build_chunk_map(&chunks);
+    assert_eq!(chunk_map["4.1"], "23.501-4.html");
+    assert_eq!(chunk_map["table-4.1-1"], "23.501-4.html");
+    assert_eq!(chunk_map["5"], "23.501-5.html");
+}
+
+#[test]
+fn test_find_chunk() {
+    let mut chunk_map = ChunkMap::new();
+    chunk_map.insert(String::from("5.6"), String::from("23.501-5.6.html"));
+
+    assert_eq!(find_chunk(&chunk_map, "23.501", "5.6"), "23.501-5.6.html");
+    assert_eq!(
+        find_chunk(&chunk_map, "23.501", "5.6.3.1"),
+        "23.501-5.6.html"
+    );
+    assert_eq!(
+        find_chunk(&chunk_map, "23.501", "table-5.6.3-1"),
+        "23.501-5.6.html"
+    );
+    assert_eq!(find_chunk(&chunk_map, "23.501", "5.7"), "23.501.html");
+}
+
+#[test]
+fn test_render_chunks_rewrites_links() {
+    let mut chunk_maps = HashMap::new();
+    let (_, chunks, _) = split_page(TEST_PAGE, "23.501", 1);
+    chunk_maps.insert(String::from("23.501"), build_chunk_map(&chunks));
+    let mut other_map = ChunkMap::new();
+    other_map.insert(String::from("4.3"), String::from("23.502-4.3.html"));
+    chunk_maps.insert(String::from("23.502"), other_map);
+
+    let pages = render_chunks(TEST_PAGE, "23.501", 1, &chunk_maps);
+    assert_eq!(pages.len(), 3);
+
+    let (file_name, front_page) = &pages[0];
+    assert_eq!(file_name, "23.501.html");
+    assert!(front_page.contains(r##"<nav><a href="23.501-4.html#4.1">4.1</a></nav>"##));
+    assert!(front_page.contains(r##"<p><a href="23.501-5.html#5">5</a></p>"##));
+    assert!(front_page.contains("var chunks = {"));
+
+    let (_, page) = &pages[1];
+    assert!(page.contains(r##"<nav><a href="#4.1">4.1</a></nav>"##));
+    assert!(page.contains(r##"<a href="../23.502/23.502-4.3.html#4.3.2">"##));
+    assert!(page.contains(r##"<nav class="chunk-nav"><span class="chunk-prev">&larr; <a href="23.501.html">Front page</a></span> | <a href="23.501.html">Contents</a> | <span class="chunk-next"><a href="23.501-5.html">5 Qux</a> &rarr;</span></nav>"##));
+    assert!(!page.contains("var chunks"));
+
+    let (_, page) = &pages[2];
+    assert!(page.contains(r##"<a href="23.501-4.html#table-4.1-1">"##));
+    assert!(page.contains(r##"<a href="#5">5</a>"##));
+}
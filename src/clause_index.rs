@@ -0,0 +1,45 @@
+use std::collections::{HashMap, HashSet};
+
+// Clause ids present in every converted document, keyed by TS number
+pub struct ClauseIndex {
+    clause_ids: HashMap<String, HashSet<String>>,
+}
+
+impl ClauseIndex {
+    pub fn new() -> ClauseIndex {
+        ClauseIndex {
+            clause_ids: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, ts_no: &str, clause_ids: HashSet<String>) {
+        self.clause_ids.insert(String::from(ts_no), clause_ids);
+    }
+
+    // A document that is not in the index can't be checked, so every clause may exist in it
+    pub fn may_contain(&self, ts_no: &str, clause_no: &str) -> bool {
+        match self.clause_ids.get(ts_no) {
+            Some(clause_ids) => clause_ids.contains(clause_no),
+            None => true,
+        }
+    }
+}
+
+#[test]
+fn test_clause_index_may_contain_known_clause_only() {
+    let mut index = ClauseIndex::new();
+    index.insert(
+        "23.501",
+        ["4.2", "A.1"].iter().map(|c| c.to_string()).collect(),
+    );
+
+    assert!(index.may_contain("23.501", "4.2"));
+    assert!(index.may_contain("23.501", "A.1"));
+    assert!(!index.may_contain("23.501", "3.5"));
+}
+
+#[test]
+fn test_clause_index_may_contain_anything_of_unknown_document() {
+    let index = ClauseIndex::new();
+    assert!(index.may_contain("23.502", "3.5"));
+}
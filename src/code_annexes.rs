@@ -0,0 +1,573 @@
+use crate::html_text;
+use crate::linkable_text;
+
+use failure::ResultExt;
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// Header cells of the table columns holding data type names
+const DATA_TYPE_HEADERS: [&str; 3] = ["data type", "type name", "type definition"];
+const DEFINITION_NAME: &str = r#"[A-Za-z_][A-Za-z0-9_]*(?:[.-][A-Za-z0-9_]+)*"#;
+
+// OpenAPI (29.5xx service based interfaces) and YANG (OAM) definitions of annexes
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum AnnexKind {
+    OpenApi,
+    Yang,
+}
+
+impl AnnexKind {
+    fn class(self) -> &'static str {
+        match self {
+            AnnexKind::OpenApi => "openapi",
+            AnnexKind::Yang => "yang",
+        }
+    }
+
+    // The first line of the definition: "openapi: 3.0.0" or "module _3gpp-common-top {"
+    fn detect(lines: &[String]) -> Option<AnnexKind> {
+        static YANG_MODULE_RE: OnceLock<Regex> = OnceLock::new();
+        let yang_module_re =
+            YANG_MODULE_RE.get_or_init(|| Regex::new(r#"^(sub)?module\s+\S+\s*(\{|$)"#).unwrap());
+
+        let first_line = lines
+            .iter()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty())?;
+        if first_line.starts_with("openapi:") {
+            Some(AnnexKind::OpenApi)
+        } else if yang_module_re.is_match(first_line) {
+            Some(AnnexKind::Yang)
+        } else {
+            None
+        }
+    }
+}
+
+// Paragraphs of a PL run: where they are in the document and their lines
+pub struct ProgramListing {
+    pub start: usize,
+    pub end: usize,
+    pub lines: Vec<String>,
+}
+
+// Replaces runs of PL paragraphs holding an OpenAPI or YANG definition by a <pre> block. Schemas
+// of the OpenAPI components and YANG typedefs, groupings and identities get an anchor, their
+// uses in the block link to it.
+pub fn render_code_annexes(content: &str) -> String {
+    println!("\tRendering OpenAPI and YANG definitions...");
+
+    let mut result = String::new();
+    let mut copied_until = 0;
+    for listing in find_program_listings(content) {
+        let kind = match AnnexKind::detect(&listing.lines) {
+            Some(kind) => kind,
+            None => continue,
+        };
+
+        result.push_str(&content[copied_until..listing.start]);
+        result.push_str(&format!("<pre class=\"{}\">", kind.class()));
+        result.push_str(&match kind {
+            AnnexKind::OpenApi => render_openapi(&listing.lines),
+            AnnexKind::Yang => render_yang(&listing.lines),
+        });
+        result.push_str("</pre>");
+        copied_until = listing.end;
+    }
+    result.push_str(&content[copied_until..]);
+
+    result
+}
+
+// Consecutive PL paragraphs, only whitespace between them
+pub fn find_program_listings(content: &str) -> Vec<ProgramListing> {
+    let pl_paragraph_re =
+        Regex::new(r#"(?is)<p\s[^>]*class="pl[-"][^>]*>(?P<content>.*?)</p>"#).unwrap();
+
+    let mut listings: Vec<ProgramListing> = Vec::new();
+    for cap in pl_paragraph_re.captures_iter(content) {
+        let paragraph = cap.get(0).unwrap();
+        let line = html_text::program_listing_text(&cap["content"]);
+
+        match listings.last_mut() {
+            Some(listing) if content[listing.end..paragraph.start()].trim().is_empty() => {
+                listing.lines.push(line);
+                listing.end = paragraph.end();
+            }
+            _ => listings.push(ProgramListing {
+                start: paragraph.start(),
+                end: paragraph.end(),
+                lines: vec![line],
+            }),
+        }
+    }
+
+    listings
+}
+
+// Keys of the "schemas:" map are the data types, "$ref: '#/components/schemas/Foo'" links to Foo
+fn render_openapi(lines: &[String]) -> String {
+    static KEY_RE: OnceLock<Regex> = OnceLock::new();
+    let key_re = KEY_RE.get_or_init(|| {
+        Regex::new(&format!(
+            r#"^(?P<indent>\s*)(?P<name>{}):"#,
+            DEFINITION_NAME
+        ))
+        .unwrap()
+    });
+    static REFERENCE_RE: OnceLock<Regex> = OnceLock::new();
+    let reference_re = REFERENCE_RE.get_or_init(|| {
+        Regex::new(&format!(
+            r#"(?P<path>#/components/schemas/)(?P<name>{})"#,
+            DEFINITION_NAME
+        ))
+        .unwrap()
+    });
+
+    let definitions = openapi_definitions(lines);
+    lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = html_text::escape(line);
+            if definitions.contains(&i) {
+                return String::from(key_re.replace(&line, |cap: &Captures| {
+                    format!(
+                        "{0}<a id=\"openapi-{1}\">{1}</a>:",
+                        &cap["indent"], &cap["name"]
+                    )
+                }));
+            }
+            String::from(reference_re.replace_all(&line, |cap: &Captures| {
+                format!(
+                    "<a href=\"#openapi-{1}\">{0}{1}</a>",
+                    &cap["path"], &cap["name"]
+                )
+            }))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Indexes of the lines defining a schema: the keys one level below "schemas:"
+fn openapi_definitions(lines: &[String]) -> Vec<usize> {
+    static KEY_RE: OnceLock<Regex> = OnceLock::new();
+    let key_re =
+        KEY_RE.get_or_init(|| Regex::new(&format!(r#"^\s*{}:\s*$"#, DEFINITION_NAME)).unwrap());
+
+    let mut definitions = Vec::new();
+    let mut schemas_indent = None;
+    let mut definition_indent = None;
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let indent = line.len() - line.trim_start().len();
+
+        if let Some(schemas) = schemas_indent {
+            if indent <= schemas {
+                schemas_indent = None;
+                definition_indent = None;
+            } else if *definition_indent.get_or_insert(indent) == indent && key_re.is_match(line) {
+                definitions.push(i);
+            }
+        }
+        if trimmed == "schemas:" {
+            schemas_indent = Some(indent);
+        }
+    }
+
+    definitions
+}
+
+// "typedef Foo {" gets an anchor, "type Foo;", "uses Foo;" and "base Foo;" link to it
+fn render_yang(lines: &[String]) -> String {
+    static DEFINITION_RE: OnceLock<Regex> = OnceLock::new();
+    let definition_re = DEFINITION_RE.get_or_init(|| {
+        Regex::new(&format!(
+            r#"^(?P<before>\s*(typedef|grouping|identity)\s+)(?P<name>{})"#,
+            DEFINITION_NAME
+        ))
+        .unwrap()
+    });
+    static USE_RE: OnceLock<Regex> = OnceLock::new();
+    let use_re = USE_RE.get_or_init(|| {
+        Regex::new(&format!(
+            r#"(?P<before>\b(type|uses|base)\s+)(?P<name>{})"#,
+            DEFINITION_NAME
+        ))
+        .unwrap()
+    });
+
+    let definitions = lines
+        .iter()
+        .filter_map(|line| definition_re.captures(line))
+        .map(|cap| String::from(&cap["name"]))
+        .collect::<Vec<_>>();
+
+    lines
+        .iter()
+        .map(|line| {
+            let line = html_text::escape(line);
+            let line = definition_re.replace(&line, |cap: &Captures| {
+                format!(
+                    "{0}<a id=\"yang-{1}\">{1}</a>",
+                    &cap["before"], &cap["name"]
+                )
+            });
+            String::from(use_re.replace_all(&line, |cap: &Captures| {
+                let name = &cap["name"];
+                if definitions.iter().any(|definition| definition == name) {
+                    format!("{0}<a href=\"#yang-{1}\">{1}</a>", &cap["before"], name)
+                } else {
+                    String::from(&cap[0])
+                }
+            }))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Links the names in the data type columns of tables to their OpenAPI or YANG definition
+pub fn add_data_type_links(content: &str) -> String {
+    println!("\tAdding data type links...");
+
+    let anchor_re = Regex::new(r#"<a id="(?P<id>(openapi|yang)-(?P<name>[^"]+))">"#).unwrap();
+    let mut definitions = HashMap::new();
+    for cap in anchor_re.captures_iter(content) {
+        definitions
+            .entry(String::from(&cap["name"]))
+            .or_insert_with(|| String::from(&cap["id"]));
+    }
+    if definitions.is_empty() {
+        return String::from(content);
+    }
+
+    let table_re = Regex::new(r#"(?is)<table[\s>].*?</table>"#).unwrap();
+    let row_re = Regex::new(r#"(?is)<tr[\s>].*?</tr>"#).unwrap();
+    let cell_re = Regex::new(r#"(?is)<t[dh][\s>].*?</t[dh]>"#).unwrap();
+    let name_re = Regex::new(&format!(r#"\b{}"#, DEFINITION_NAME)).unwrap();
+
+    let link_names = |cell: &str| {
+        linkable_text::replace_linkable_text(cell, |text, _| {
+            String::from(name_re.replace_all(
+                text,
+                |cap: &Captures| match definitions.get(&cap[0]) {
+                    Some(id) => format!("<a href=\"#{}\">{}</a>", id, &cap[0]),
+                    None => String::from(&cap[0]),
+                },
+            ))
+        })
+    };
+
+    let mut result = String::new();
+    let mut copied_until = 0;
+    for table in table_re.find_iter(content) {
+        let mut columns = Vec::new();
+        for (i, row) in row_re.find_iter(table.as_str()).enumerate() {
+            let cells = cell_re.find_iter(row.as_str()).collect::<Vec<_>>();
+            if i == 0 {
+                columns = cells
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, cell)| {
+                        let header = html_text::to_plain_text(cell.as_str()).to_lowercase();
+                        DATA_TYPE_HEADERS.contains(&header.as_str())
+                    })
+                    .map(|(column, _)| column)
+                    .collect();
+                continue;
+            }
+
+            for (column, cell) in cells.iter().enumerate() {
+                if !columns.contains(&column) {
+                    continue;
+                }
+                let start = table.start() + row.start() + cell.start();
+                result.push_str(&content[copied_until..start]);
+                result.push_str(&link_names(cell.as_str()));
+                copied_until = start + cell.as_str().len();
+            }
+        }
+    }
+    result.push_str(&content[copied_until..]);
+
+    result
+}
+
+// Writes every OpenAPI definition to a TS<number>_<API title>.yaml file and every YANG module
+// to a <module name>.yang file, warns about the ones that are not valid
+pub fn save_code_annexes(
+    output_dir: &str,
+    ts_no: &str,
+    content: &str,
+) -> Result<(), failure::Error> {
+    static PRE_RE: OnceLock<Regex> = OnceLock::new();
+    let pre_re = PRE_RE.get_or_init(|| {
+        Regex::new(r#"(?s)<pre class="(?P<class>openapi|yang)">(?P<code>.*?)</pre>"#).unwrap()
+    });
+    static TAG_RE: OnceLock<Regex> = OnceLock::new();
+    let tag_re = TAG_RE.get_or_init(|| Regex::new(r#"(?s)<[^<>]+?>"#).unwrap());
+
+    for (i, cap) in pre_re.captures_iter(content).enumerate() {
+        let mut code = html_text::unescape(&tag_re.replace_all(&cap["code"], ""));
+        code.push('\n');
+
+        let (file_name, validation) = if &cap["class"] == "openapi" {
+            (
+                openapi_file_name(&code, ts_no, i + 1),
+                validate_openapi(&code),
+            )
+        } else {
+            (yang_file_name(&code, ts_no, i + 1), validate_yang(&code))
+        };
+        if let Err(error) = validation {
+            println!("\tWarning: {} is not valid: {}", file_name, error);
+        }
+
+        println!("\tSaving {}...", file_name);
+        let path = format!("{}/{}", output_dir, file_name);
+        std::fs::write(&path, code)
+            .with_context(|_| format!("could not write definition file `{}`", path))?;
+    }
+
+    Ok(())
+}
+
+// "TS29510_Nnrf_NFManagement.yaml", the name 3GPP uses for the API of the "info" title
+fn openapi_file_name(code: &str, ts_no: &str, index: usize) -> String {
+    static TITLE_RE: OnceLock<Regex> = OnceLock::new();
+    let title_re = TITLE_RE.get_or_init(|| {
+        Regex::new(r#"(?m)^\s+title:\s*['"]?(?P<title>[^'"\n]+?)['"]?\s*$"#).unwrap()
+    });
+    static UNSAFE_RE: OnceLock<Regex> = OnceLock::new();
+    let unsafe_re = UNSAFE_RE.get_or_init(|| Regex::new(r#"[^A-Za-z0-9_.-]+"#).unwrap());
+
+    let number = ts_no.replace('.', "");
+    match title_re.captures(code) {
+        Some(cap) => format!(
+            "TS{}_{}.yaml",
+            number,
+            unsafe_re.replace_all(&cap["title"], "_")
+        ),
+        None => format!("TS{}_{}.yaml", number, index),
+    }
+}
+
+fn yang_file_name(code: &str, ts_no: &str, index: usize) -> String {
+    static MODULE_RE: OnceLock<Regex> = OnceLock::new();
+    let module_re = MODULE_RE
+        .get_or_init(|| Regex::new(r#"(?m)^\s*(sub)?module\s+(?P<name>[A-Za-z0-9_.-]+)"#).unwrap());
+
+    match module_re.captures(code) {
+        Some(cap) => format!("{}.yang", &cap["name"]),
+        None => format!("TS{}_{}.yang", ts_no.replace('.', ""), index),
+    }
+}
+
+fn validate_openapi(code: &str) -> Result<(), String> {
+    let documents =
+        yaml_rust::YamlLoader::load_from_str(code).map_err(|error| error.to_string())?;
+
+    match documents.first() {
+        Some(document) if !document["openapi"].is_badvalue() => Ok(()),
+        _ => Err(String::from("the \"openapi\" field is missing")),
+    }
+}
+
+// Braces balanced outside of strings and comments, nothing after the module
+fn validate_yang(code: &str) -> Result<(), String> {
+    let mut chars = code.chars().peekable();
+    let mut line = 1;
+    let mut depth = 0usize;
+    let mut closed = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let start_line = line;
+                let mut previous = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if previous == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            previous = c;
+                        }
+                        None => return Err(format!("unterminated comment on line {}", start_line)),
+                    }
+                }
+            }
+            '"' | '\'' => {
+                let start_line = line;
+                loop {
+                    match chars.next() {
+                        Some('\\') if c == '"' => {
+                            chars.next();
+                        }
+                        Some(end) if end == c => break,
+                        Some(other) => {
+                            if other == '\n' {
+                                line += 1;
+                            }
+                        }
+                        None => return Err(format!("unterminated string on line {}", start_line)),
+                    }
+                }
+            }
+            '{' => {
+                if closed {
+                    return Err(format!(
+                        "statement after the end of the module on line {}",
+                        line
+                    ));
+                }
+                depth += 1;
+            }
+            '}' => {
+                if depth == 0 {
+                    return Err(format!("unexpected \"}}\" on line {}", line));
+                }
+                depth -= 1;
+                closed = depth == 0;
+            }
+            c if closed && !c.is_whitespace() => {
+                return Err(format!(
+                    "statement after the end of the module on line {}",
+                    line
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    if depth > 0 || !closed {
+        return Err(String::from("the module is not closed"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn pl_paragraphs(lines: &[&str]) -> String {
+    lines
+        .iter()
+        .map(|line| format!("<p class=\"PL-western\">{}</p>\n", line))
+        .collect()
+}
+
+#[test]
+fn test_render_openapi() {
+    let source = format!(
+        "<p class=\"western\">Foo</p>\n{}<p class=\"western\">Bar</p>",
+        pl_paragraphs(&[
+            "openapi: 3.0.0",
+            "info:",
+            "  title: Nnrf_NFManagement",
+            "components:",
+            "  schemas:",
+            "    NFProfile:",
+            "      type: object",
+            "      properties:",
+            "        plmnList:",
+            "          $ref: '#/components/schemas/PlmnId'",
+            "    PlmnId:",
+            "      type: string",
+        ])
+    );
+    let expected = r##"<p class="western">Foo</p>
+<pre class="openapi">openapi: 3.0.0
+info:
+  title: Nnrf_NFManagement
+components:
+  schemas:
+    <a id="openapi-NFProfile">NFProfile</a>:
+      type: object
+      properties:
+        plmnList:
+          $ref: '<a href="#openapi-PlmnId">#/components/schemas/PlmnId</a>'
+    <a id="openapi-PlmnId">PlmnId</a>:
+      type: string</pre>
+<p class="western">Bar</p>"##;
+
+    assert_eq!(render_code_annexes(&source), expected);
+}
+
+#[test]
+fn test_render_yang() {
+    let source = pl_paragraphs(&[
+        "module _3gpp-foo {",
+        "  typedef Foo-Type { type string; }",
+        "  grouping BarGrp { leaf foo { type Foo-Type; } }",
+        "  uses BarGrp;",
+        "}",
+    ]);
+    let expected = r##"<pre class="yang">module _3gpp-foo {
+  typedef <a id="yang-Foo-Type">Foo-Type</a> { type string; }
+  grouping <a id="yang-BarGrp">BarGrp</a> { leaf foo { type <a href="#yang-Foo-Type">Foo-Type</a>; } }
+  uses <a href="#yang-BarGrp">BarGrp</a>;
+}</pre>
+"##;
+
+    assert_eq!(render_code_annexes(&source), expected);
+}
+
+#[test]
+fn test_render_code_annexes_skips_other_listings() {
+    let source = pl_paragraphs(&["Foo ::= INTEGER"]);
+    assert_eq!(render_code_annexes(&source), source);
+}
+
+#[test]
+fn test_add_data_type_links() {
+    let source = r##"<table><tr><td><p>Attribute name</p></td><td><p>Data type</p></td><td><p>Description</p></td></tr>
+<tr><td><p>plmnList</p></td><td><p>array(PlmnId)</p></td><td><p>PlmnId of the NF</p></td></tr></table>
+<pre class="openapi">    <a id="openapi-PlmnId">PlmnId</a>:</pre>"##;
+    let expected = r##"<table><tr><td><p>Attribute name</p></td><td><p>Data type</p></td><td><p>Description</p></td></tr>
+<tr><td><p>plmnList</p></td><td><p>array(<a href="#openapi-PlmnId">PlmnId</a>)</p></td><td><p>PlmnId of the NF</p></td></tr></table>
+<pre class="openapi">    <a id="openapi-PlmnId">PlmnId</a>:</pre>"##;
+
+    assert_eq!(add_data_type_links(source), expected);
+}
+
+#[test]
+fn test_file_names() {
+    let code = "openapi: 3.0.0\ninfo:\n  version: 1.0.0\n  title: 'Nnrf_NFManagement'\n";
+    assert_eq!(
+        openapi_file_name(code, "29.510", 1),
+        "TS29510_Nnrf_NFManagement.yaml"
+    );
+    assert_eq!(
+        openapi_file_name("openapi: 3.0.0\n", "29.510", 2),
+        "TS29510_2.yaml"
+    );
+    assert_eq!(
+        yang_file_name("module _3gpp-common-top {\n}\n", "28.623", 1),
+        "_3gpp-common-top.yang"
+    );
+}
+
+#[test]
+fn test_validate_openapi() {
+    assert!(validate_openapi("openapi: 3.0.0\ninfo:\n  title: Foo\n").is_ok());
+    assert!(validate_openapi("info:\n  title: Foo\n").is_err());
+    assert!(validate_openapi("openapi: 3.0.0\ninfo:\n  title: [Foo\n").is_err());
+}
+
+#[test]
+fn test_validate_yang() {
+    assert!(validate_yang("module foo {\n  description \"a } b\"; // }\n  /* { */\n}\n").is_ok());
+    assert!(validate_yang("module foo {\n  leaf bar {\n}\n").is_err());
+    assert!(validate_yang("module foo {\n}\n}\n").is_err());
+    assert!(validate_yang("module foo {\n  description \"bar;\n}\n").is_err());
+}
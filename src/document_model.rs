@@ -0,0 +1,866 @@
+use crate::html_text;
+use crate::html_tokens::{self, Token};
+use crate::spec_metadata::SpecMetadata;
+
+use regex::Regex;
+use serde::Serialize;
+use std::sync::OnceLock;
+
+// Tags removed with their content: scripts, the reference controls and tables of contents,
+// which repeat the headings
+const SKIPPED_TAGS: [&str; 5] = ["script", "style", "title", "button", "nav"];
+const TOC_DIV_IDS: [&str; 2] = ["toc", "Table of Contents1"];
+
+// A document as clauses with typed blocks, written as JSON for other tools
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DocumentModel {
+    pub metadata: SpecMetadata,
+    // Cover page and everything else before the first heading
+    pub blocks: Vec<Block>,
+    pub clauses: Vec<Clause>,
+}
+
+// Headings without a clause number, e.g. "Foreword", have no id
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Clause {
+    pub id: Option<String>,
+    pub heading: String,
+    pub level: usize,
+    pub blocks: Vec<Block>,
+    pub clauses: Vec<Clause>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Block {
+    Paragraph {
+        content: Vec<Inline>,
+    },
+    List {
+        ordered: bool,
+        items: Vec<ListItem>,
+    },
+    Table {
+        id: Option<String>,
+        caption: Option<String>,
+        rows: Vec<Vec<TableCell>>,
+    },
+    Figure {
+        id: Option<String>,
+        caption: Option<String>,
+        images: Vec<String>,
+    },
+    Note {
+        label: String,
+        content: Vec<Inline>,
+    },
+    EditorsNote {
+        content: Vec<Inline>,
+    },
+    Code {
+        text: String,
+    },
+}
+
+// Items of nested lists (or B2 paragraphs in a B1 list) have a higher level
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ListItem {
+    pub level: usize,
+    pub content: Vec<Inline>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct TableCell {
+    pub content: Vec<Inline>,
+    pub colspan: usize,
+    pub rowspan: usize,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Inline {
+    Text {
+        text: String,
+    },
+    // A clause, table or figure of a spec, or the spec itself when there is no target
+    Reference {
+        text: String,
+        ts_no: String,
+        target: Option<String>,
+    },
+    Link {
+        text: String,
+        url: String,
+    },
+}
+
+pub fn build_document_model(content: &str, metadata: &SpecMetadata) -> DocumentModel {
+    println!("\tBuilding document model...");
+
+    let body_re = Regex::new(r#"(?is)<body[^>]*>(?P<body>.*)</body>"#).unwrap();
+    let body = body_re
+        .captures(content)
+        .map(|cap| String::from(&cap["body"]))
+        .unwrap_or_else(|| String::from(content));
+
+    let mut builder = ModelBuilder {
+        ts_no: metadata.ts_no.clone(),
+        ..ModelBuilder::default()
+    };
+    builder.write(&body);
+
+    let (blocks, clauses) = builder.finish();
+    DocumentModel {
+        metadata: metadata.clone(),
+        blocks,
+        clauses,
+    }
+}
+
+#[derive(Default)]
+struct TableState {
+    depth: usize,
+    rows: Vec<Vec<TableCell>>,
+    cell: Option<TableCell>,
+}
+
+#[derive(Default)]
+struct ModelBuilder {
+    ts_no: String,
+    front_blocks: Vec<Block>,
+    // Open clauses, each one nested in the previous one
+    clauses: Vec<Clause>,
+    roots: Vec<Clause>,
+    inline: Vec<Inline>,
+    // Href of the open link and where its text starts in the inline content
+    link: Option<(String, usize)>,
+    heading: Option<(usize, Option<String>)>,
+    paragraph_class: Option<String>,
+    // Caption of a figure or table in the current paragraph
+    caption_id: Option<String>,
+    images: Vec<String>,
+    // Open lists, the outermost one is ordered or not, and their items
+    lists: Vec<bool>,
+    list_ordered: bool,
+    list_items: Vec<ListItem>,
+    pre: Option<String>,
+    table: Option<TableState>,
+    table_caption: Option<(String, String)>,
+    skipped: Option<(String, usize)>,
+    last_block_is_code_paragraph: bool,
+}
+
+impl ModelBuilder {
+    fn write(&mut self, html: &str) {
+        for (_, token) in html_tokens::tokenize(html) {
+            match token {
+                Token::Comment => {}
+                Token::Text(text) => {
+                    if self.skipped.is_none() {
+                        self.write_text(&html_text::unescape(text));
+                    }
+                }
+                Token::Open { name, attrs } => self.open_tag(&name, attrs),
+                Token::Close { name } => self.close_tag(&name),
+            }
+        }
+    }
+
+    fn finish(mut self) -> (Vec<Block>, Vec<Clause>) {
+        self.end_paragraph();
+        self.end_list();
+        self.push_table_caption();
+        while !self.clauses.is_empty() {
+            self.close_clause();
+        }
+        (self.front_blocks, self.roots)
+    }
+
+    fn write_text(&mut self, text: &str) {
+        if let Some(pre) = &mut self.pre {
+            pre.push_str(text);
+            return;
+        }
+        if self.is_code_paragraph() {
+            push_text(&mut self.inline, text);
+            return;
+        }
+
+        static WHITESPACE_RE: OnceLock<Regex> = OnceLock::new();
+        let whitespace_re = WHITESPACE_RE.get_or_init(|| Regex::new(r#"\s+"#).unwrap());
+        push_text(&mut self.inline, &whitespace_re.replace_all(text, " "));
+    }
+
+    fn open_tag(&mut self, name: &str, attrs: &str) {
+        if let Some((skipped_tag, depth)) = &mut self.skipped {
+            if skipped_tag == name {
+                *depth += 1;
+            }
+            return;
+        }
+        let id = attribute(attrs, "id");
+        if SKIPPED_TAGS.contains(&name)
+            || attribute(attrs, "class").as_deref() == Some("reference-controls")
+            || (name == "div" && id.as_deref().is_some_and(|id| TOC_DIV_IDS.contains(&id)))
+        {
+            self.skipped = Some((String::from(name), 1));
+            return;
+        }
+
+        if let Some(table) = &mut self.table {
+            match name {
+                "table" => table.depth += 1,
+                "tr" if table.depth == 1 => table.rows.push(Vec::new()),
+                "td" | "th" if table.depth == 1 => {
+                    let span = |attr| {
+                        attribute(attrs, attr)
+                            .and_then(|span| span.parse().ok())
+                            .unwrap_or(1)
+                    };
+                    table.cell = Some(TableCell {
+                        content: Vec::new(),
+                        colspan: span("colspan"),
+                        rowspan: span("rowspan"),
+                    });
+                }
+                "p" | "br" | "li" | "tr" => push_text(&mut self.inline, "\n"),
+                "a" => self.open_link(attrs),
+                _ => {}
+            }
+            return;
+        }
+
+        if let Some(level) = html_tokens::heading_level(name) {
+            self.end_paragraph();
+            self.end_list();
+            self.push_table_caption();
+            self.heading = Some((level, id));
+            return;
+        }
+
+        match name {
+            "p" | "div" | "blockquote" if self.lists.is_empty() => {
+                self.end_paragraph();
+                if name == "p" {
+                    self.paragraph_class = Some(attribute(attrs, "class").unwrap_or_default());
+                }
+            }
+            "p" => push_text(&mut self.inline, "\n"),
+            "ul" | "ol" => {
+                if self.lists.is_empty() {
+                    self.end_paragraph();
+                    self.list_ordered = name == "ol";
+                }
+                self.end_list_item();
+                self.lists.push(name == "ol");
+            }
+            "li" => self.end_list_item(),
+            "pre" => {
+                self.end_paragraph();
+                self.pre = Some(String::new());
+            }
+            "table" => {
+                self.end_paragraph();
+                self.end_list();
+                self.table = Some(TableState {
+                    depth: 1,
+                    ..TableState::default()
+                });
+            }
+            "br" => push_text(&mut self.inline, "\n"),
+            "img" => {
+                if let Some(src) = attribute(attrs, "src") {
+                    self.images.push(src);
+                }
+            }
+            "a" => self.open_link(attrs),
+            "b" if id.is_some() => self.caption_id = id,
+            _ => {}
+        }
+    }
+
+    fn close_tag(&mut self, name: &str) {
+        if let Some((skipped_tag, depth)) = &mut self.skipped {
+            if skipped_tag == name {
+                *depth -= 1;
+                if *depth == 0 {
+                    self.skipped = None;
+                }
+            }
+            return;
+        }
+
+        if name == "a" {
+            self.close_link();
+            return;
+        }
+
+        if let Some(table) = &mut self.table {
+            match name {
+                "td" | "th" if table.depth == 1 => {
+                    if let Some(mut cell) = table.cell.take() {
+                        cell.content = trim_inlines(std::mem::take(&mut self.inline));
+                        if let Some(row) = table.rows.last_mut() {
+                            row.push(cell);
+                        }
+                    }
+                }
+                "table" => {
+                    table.depth -= 1;
+                    if table.depth == 0 {
+                        let table = self.table.take().unwrap();
+                        let (id, caption) = match self.table_caption.take() {
+                            Some((id, caption)) => (Some(id), Some(caption)),
+                            None => (None, None),
+                        };
+                        self.push_block(Block::Table {
+                            id,
+                            caption,
+                            rows: table.rows,
+                        });
+                    }
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        if html_tokens::heading_level(name).is_some() {
+            if let Some((level, id)) = self.heading.take() {
+                let heading = plain_text(&std::mem::take(&mut self.inline));
+                self.open_clause(Clause {
+                    id,
+                    heading,
+                    level,
+                    blocks: Vec::new(),
+                    clauses: Vec::new(),
+                });
+            }
+            return;
+        }
+
+        match name {
+            "p" | "div" | "blockquote" if self.lists.is_empty() => self.end_paragraph(),
+            "li" => self.end_list_item(),
+            "ul" | "ol" => {
+                self.end_list_item();
+                self.lists.pop();
+                if self.lists.is_empty() {
+                    self.end_list();
+                }
+            }
+            "pre" => {
+                if let Some(pre) = self.pre.take() {
+                    self.push_block(Block::Code {
+                        text: String::from(pre.trim_matches('\n')),
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn open_link(&mut self, attrs: &str) {
+        if let Some(href) = attribute(attrs, "href") {
+            // An empty text keeps the text of the link apart from the text before it
+            self.link = Some((href, self.inline.len()));
+            self.inline.push(Inline::Text {
+                text: String::new(),
+            });
+        }
+    }
+
+    // The text written since the link was opened becomes the text of the link
+    fn close_link(&mut self) {
+        let (href, start) = match self.link.take() {
+            Some(link) => link,
+            None => return,
+        };
+        if start > self.inline.len() {
+            return;
+        }
+
+        let text = plain_text(&self.inline.split_off(start));
+        if text.is_empty() {
+            return;
+        }
+        let inline = link_inline(&href, text, &self.ts_no);
+        self.inline.push(inline);
+    }
+
+    fn is_code_paragraph(&self) -> bool {
+        static CODE_CLASS_RE: OnceLock<Regex> = OnceLock::new();
+        let code_class_re = CODE_CLASS_RE.get_or_init(|| Regex::new(r#"(?i)^pl(-|$)"#).unwrap());
+        self.paragraph_class
+            .as_deref()
+            .is_some_and(|class| code_class_re.is_match(class))
+    }
+
+    // Classifies the finished paragraph: figure, caption, note, list item, code or plain text
+    fn end_paragraph(&mut self) {
+        let is_code = self.is_code_paragraph();
+        let class = self.paragraph_class.take().unwrap_or_default();
+        let caption_id = self.caption_id.take();
+        let images = std::mem::take(&mut self.images);
+        self.link = None;
+
+        if is_code {
+            let text = plain_code(&std::mem::take(&mut self.inline));
+            let follows_code_paragraph = self.last_block_is_code_paragraph;
+            match self.current_blocks().last_mut() {
+                Some(Block::Code { text: code }) if follows_code_paragraph => {
+                    code.push('\n');
+                    code.push_str(&text);
+                }
+                _ => self.push_block(Block::Code { text }),
+            }
+            self.last_block_is_code_paragraph = true;
+            return;
+        }
+
+        let mut content = trim_inlines(std::mem::take(&mut self.inline));
+        let text = plain_text(&content);
+
+        if !images.is_empty() {
+            self.push_block(Block::Figure {
+                id: None,
+                caption: None,
+                images,
+            });
+            return;
+        }
+        if text.is_empty() {
+            return;
+        }
+
+        if let Some(id) = caption_id {
+            if text.starts_with("Table") {
+                self.push_table_caption();
+                self.table_caption = Some((id, text));
+                return;
+            }
+            if text.starts_with("Figure") {
+                // Figure captions are below the figure
+                match self.current_blocks().last_mut() {
+                    Some(Block::Figure {
+                        id: figure_id,
+                        caption: figure_caption @ None,
+                        ..
+                    }) => {
+                        *figure_id = Some(id);
+                        *figure_caption = Some(text);
+                    }
+                    _ => self.push_block(Block::Figure {
+                        id: Some(id),
+                        caption: Some(text),
+                        images: Vec::new(),
+                    }),
+                }
+                return;
+            }
+        }
+
+        static NOTE_RE: OnceLock<Regex> = OnceLock::new();
+        static EDITORS_NOTE_RE: OnceLock<Regex> = OnceLock::new();
+        static LIST_CLASS_RE: OnceLock<Regex> = OnceLock::new();
+        static LIST_MARKER_RE: OnceLock<Regex> = OnceLock::new();
+        let note_re =
+            NOTE_RE.get_or_init(|| Regex::new(r#"^(?P<label>NOTE(\s+\d+)?)\s*:\s*"#).unwrap());
+        let editors_note_re =
+            EDITORS_NOTE_RE.get_or_init(|| Regex::new(r#"^Editor(')?s\s+[Nn]ote\s*:\s*"#).unwrap());
+        // B1 to B5 paragraphs of 3GPP: "-	Lorem", "1)	Lorem" or "a)	Lorem"
+        let list_class_re =
+            LIST_CLASS_RE.get_or_init(|| Regex::new(r#"(?i)^b(?P<level>\d)(-|$)"#).unwrap());
+        let list_marker_re = LIST_MARKER_RE.get_or_init(|| {
+            Regex::new(r#"^((?P<bullet>[-•*–])|\(?(?P<number>[0-9a-zA-Z]{1,3})[).])\s+"#).unwrap()
+        });
+
+        if let Some(cap) = note_re.captures(&text) {
+            let label = String::from(&cap["label"]);
+            strip_prefix(&mut content, cap.get(0).unwrap().as_str());
+            self.push_block(Block::Note { label, content });
+        } else if let Some(cap) = editors_note_re.captures(&text) {
+            strip_prefix(&mut content, cap.get(0).unwrap().as_str());
+            self.push_block(Block::EditorsNote { content });
+        } else if let (Some(class_cap), Some(marker_cap)) = (
+            list_class_re.captures(&class),
+            list_marker_re.captures(&text),
+        ) {
+            let level = class_cap["level"].parse().unwrap();
+            let ordered = marker_cap.name("number").is_some();
+            strip_prefix(&mut content, marker_cap.get(0).unwrap().as_str());
+            let item = ListItem { level, content };
+            match self.current_blocks().last_mut() {
+                Some(Block::List { items, .. }) => items.push(item),
+                _ => self.push_block(Block::List {
+                    ordered,
+                    items: vec![item],
+                }),
+            }
+        } else {
+            self.push_block(Block::Paragraph { content });
+        }
+    }
+
+    fn end_list_item(&mut self) {
+        if self.lists.is_empty() {
+            return;
+        }
+        let content = trim_inlines(std::mem::take(&mut self.inline));
+        if !content.is_empty() {
+            self.list_items.push(ListItem {
+                level: self.lists.len(),
+                content,
+            });
+        }
+    }
+
+    fn end_list(&mut self) {
+        if self.list_items.is_empty() {
+            return;
+        }
+        let items = std::mem::take(&mut self.list_items);
+        self.push_block(Block::List {
+            ordered: self.list_ordered,
+            items,
+        });
+    }
+
+    // A table caption without a table is kept as a paragraph
+    fn push_table_caption(&mut self) {
+        if let Some((_, caption)) = self.table_caption.take() {
+            self.push_block(Block::Paragraph {
+                content: vec![Inline::Text { text: caption }],
+            });
+        }
+    }
+
+    fn push_block(&mut self, block: Block) {
+        if !matches!(block, Block::Table { .. }) {
+            self.push_table_caption();
+        }
+        self.last_block_is_code_paragraph = false;
+        self.current_blocks().push(block);
+    }
+
+    fn current_blocks(&mut self) -> &mut Vec<Block> {
+        match self.clauses.last_mut() {
+            Some(clause) => &mut clause.blocks,
+            None => &mut self.front_blocks,
+        }
+    }
+
+    // Clauses are nested by their heading level, like the table of contents
+    fn open_clause(&mut self, clause: Clause) {
+        while self
+            .clauses
+            .last()
+            .is_some_and(|last| last.level >= clause.level)
+        {
+            self.close_clause();
+        }
+        self.clauses.push(clause);
+    }
+
+    fn close_clause(&mut self) {
+        let clause = self.clauses.pop().unwrap();
+        match self.clauses.last_mut() {
+            Some(parent) => parent.clauses.push(clause),
+            None => self.roots.push(clause),
+        }
+    }
+}
+
+// "#4.3" and "../23.502/23.502.html#4.3" are references, other hrefs are plain links
+fn link_inline(href: &str, text: String, ts_no: &str) -> Inline {
+    static DOCUMENT_RE: OnceLock<Regex> = OnceLock::new();
+    let document_re = DOCUMENT_RE.get_or_init(|| {
+        Regex::new(r#"^\.\./(?P<ts_no>[^/]+)/(?P<file>[^/#]+)\.html(#(?P<target>.*))?$"#).unwrap()
+    });
+
+    if let Some(target) = href.strip_prefix('#') {
+        return Inline::Reference {
+            text,
+            ts_no: String::from(ts_no),
+            target: Some(String::from(target)),
+        };
+    }
+
+    match document_re.captures(href) {
+        Some(cap) if cap["file"] == cap["ts_no"] => Inline::Reference {
+            text,
+            ts_no: String::from(&cap["ts_no"]),
+            target: cap
+                .name("target")
+                .map(|target| String::from(target.as_str())),
+        },
+        _ => Inline::Link {
+            text,
+            url: String::from(href),
+        },
+    }
+}
+
+fn push_text(inline: &mut Vec<Inline>, text: &str) {
+    if let Some(Inline::Text { text: last }) = inline.last_mut() {
+        last.push_str(text);
+    } else {
+        inline.push(Inline::Text {
+            text: String::from(text),
+        });
+    }
+}
+
+// Removes the whitespace around the content and empty texts
+fn trim_inlines(inline: Vec<Inline>) -> Vec<Inline> {
+    let mut inline = inline
+        .into_iter()
+        .map(|item| match item {
+            Inline::Text { text } => Inline::Text {
+                text: text.replace('\n', " ").replace("  ", " "),
+            },
+            item => item,
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(Inline::Text { text }) = inline.first_mut() {
+        *text = String::from(text.trim_start());
+    }
+    if let Some(Inline::Text { text }) = inline.last_mut() {
+        *text = String::from(text.trim_end());
+    }
+    inline.retain(|item| !matches!(item, Inline::Text { text } if text.is_empty()));
+    inline
+}
+
+fn strip_prefix(inline: &mut Vec<Inline>, prefix: &str) {
+    if let Some(Inline::Text { text }) = inline.first_mut() {
+        if let Some(rest) = text.strip_prefix(prefix) {
+            *text = String::from(rest);
+        }
+    }
+    inline.retain(|item| !matches!(item, Inline::Text { text } if text.is_empty()));
+}
+
+fn inline_text(item: &Inline) -> &str {
+    match item {
+        Inline::Text { text } | Inline::Reference { text, .. } | Inline::Link { text, .. } => text,
+    }
+}
+
+fn plain_text(inline: &[Inline]) -> String {
+    let text = inline.iter().map(inline_text).collect::<String>();
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Code keeps its spacing, only the line break LibreOffice adds after the tag is removed
+fn plain_code(inline: &[Inline]) -> String {
+    let text = inline.iter().map(inline_text).collect::<String>();
+    String::from(text.trim_start_matches('\n').trim_end())
+}
+
+// Unescaped value of the attribute
+fn attribute(attrs: &str, name: &str) -> Option<String> {
+    html_tokens::attribute(attrs, name).map(html_text::unescape)
+}
+
+#[cfg(test)]
+fn text(text: &str) -> Inline {
+    Inline::Text {
+        text: String::from(text),
+    }
+}
+
+#[test]
+fn test_build_document_model_clauses() {
+    let source = r##"<body><p class="western">3GPP TS 23.501</p>
+<div id="toc" class="generated-toc"><p class="toc-title">Contents</p><ul><li><a href="#4">4 General</a></li></ul></div>
+<h1 class="western">Foreword</h1>
+<h1 id="4"  class="western">4	General<span class="reference-controls"><a class="permalink" href="#4"></a><button class="copy-reference" type="button"></button></span></h1>
+<p class="western">The AMF uses <a href="../23.502/23.502.html#4.3">TS 23.502 clause 4.3</a>, <a href="#4.1">clause 4.1</a> and <a href="http://example.com">this</a>.</p>
+<h2 id="4.1"  class="western">4.1	Foo</h2>
+<p class="western">NOTE 1:	Lorem ipsum.</p>
+<p class="western">Editor's note:	Dolor.</p>
+<h1 id="5"  class="western">5	Bar</h1>
+</body>"##;
+
+    let model = build_document_model(source, &SpecMetadata::default());
+    assert_eq!(
+        model.blocks,
+        vec![Block::Paragraph {
+            content: vec![text("3GPP TS 23.501")]
+        }]
+    );
+
+    let headings = model
+        .clauses
+        .iter()
+        .map(|clause| (clause.id.as_deref(), clause.heading.as_str(), clause.level))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        headings,
+        vec![
+            (None, "Foreword", 1),
+            (Some("4"), "4 General", 1),
+            (Some("5"), "5 Bar", 1)
+        ]
+    );
+
+    let general = &model.clauses[1];
+    assert_eq!(
+        general.blocks,
+        vec![Block::Paragraph {
+            content: vec![
+                text("The AMF uses "),
+                Inline::Reference {
+                    text: String::from("TS 23.502 clause 4.3"),
+                    ts_no: String::from("23.502"),
+                    target: Some(String::from("4.3")),
+                },
+                text(", "),
+                Inline::Reference {
+                    text: String::from("clause 4.1"),
+                    ts_no: String::new(),
+                    target: Some(String::from("4.1")),
+                },
+                text(" and "),
+                Inline::Link {
+                    text: String::from("this"),
+                    url: String::from("http://example.com"),
+                },
+                text("."),
+            ]
+        }]
+    );
+
+    assert_eq!(general.clauses.len(), 1);
+    assert_eq!(
+        general.clauses[0].blocks,
+        vec![
+            Block::Note {
+                label: String::from("NOTE 1"),
+                content: vec![text("Lorem ipsum.")]
+            },
+            Block::EditorsNote {
+                content: vec![text("Dolor.")]
+            }
+        ]
+    );
+}
+
+#[test]
+fn test_build_document_model_annex_clauses() {
+    let source = r#"<h8 id="A"  class="western">Annex A (normative):<br/>Foo</h8>
+<p class="western">Bar.</p>
+<h9 id="A.1"  class="western">A.1	Baz</h9>
+<p class="western">Qux.</p>
+"#;
+
+    let model = build_document_model(source, &SpecMetadata::default());
+    assert_eq!(model.clauses.len(), 1);
+    let annex = &model.clauses[0];
+    assert_eq!((annex.id.as_deref(), annex.level), (Some("A"), 8));
+    assert_eq!(
+        annex.blocks,
+        vec![Block::Paragraph {
+            content: vec![text("Bar.")]
+        }]
+    );
+    assert_eq!(annex.clauses.len(), 1);
+    assert_eq!(
+        (
+            annex.clauses[0].id.as_deref(),
+            annex.clauses[0].heading.as_str()
+        ),
+        (Some("A.1"), "A.1 Baz")
+    );
+    assert_eq!(
+        annex.clauses[0].blocks,
+        vec![Block::Paragraph {
+            content: vec![text("Qux.")]
+        }]
+    );
+}
+
+#[test]
+fn test_build_document_model_blocks() {
+    let source = r#"<h1 id="4"  class="western">4	General</h1>
+<p class="B1-western">-	Foo;</p>
+<p class="B2-western">a)	Bar.</p>
+<ul><li><p>Baz</p></li><li>Qux<ol><li>Quux</li></ol></li></ul>
+<p class="TH"><b id="table-4-1">Table 4-1: Foo</b></p>
+<table><tr><th>A</th><th>B</th></tr><tr><td colspan="2"><p>C</p><p>D</p></td></tr></table>
+<p class="western"><img src="foo.png" name="Image1"></p>
+<p class="TF"><b id="4-1">Figure 4-1: Bar</b></p>
+<p class="PL-western">Foo ::= SEQUENCE {</p>
+<p class="PL-western">	bar	INTEGER</p>
+<p class="PL-western">}</p>
+<pre>baz
+</pre>"#;
+
+    let model = build_document_model(source, &SpecMetadata::default());
+    let cell = |content: &str, colspan| TableCell {
+        content: vec![text(content)],
+        colspan,
+        rowspan: 1,
+    };
+    let item = |level, content: &str| ListItem {
+        level,
+        content: vec![text(content)],
+    };
+
+    assert_eq!(
+        model.clauses[0].blocks,
+        vec![
+            Block::List {
+                ordered: false,
+                items: vec![item(1, "Foo;"), item(2, "Bar.")]
+            },
+            Block::List {
+                ordered: false,
+                items: vec![item(1, "Baz"), item(1, "Qux"), item(2, "Quux")]
+            },
+            Block::Table {
+                id: Some(String::from("table-4-1")),
+                caption: Some(String::from("Table 4-1: Foo")),
+                rows: vec![vec![cell("A", 1), cell("B", 1)], vec![cell("C D", 2)]]
+            },
+            Block::Figure {
+                id: Some(String::from("4-1")),
+                caption: Some(String::from("Figure 4-1: Bar")),
+                images: vec![String::from("foo.png")]
+            },
+            Block::Code {
+                text: String::from("Foo ::= SEQUENCE {\n\tbar\tINTEGER\n}")
+            },
+            Block::Code {
+                text: String::from("baz")
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_document_model_json() {
+    let source = r#"<h1 id="4"  class="western">4	General</h1><p>See <a href="../23.502/23.502.html">TS 23.502</a>.</p>"#;
+    let model = build_document_model(source, &SpecMetadata::default());
+    let json = serde_json::to_value(&model.clauses[0]).unwrap();
+
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "id": "4",
+            "heading": "4 General",
+            "level": 1,
+            "blocks": [{
+                "type": "paragraph",
+                "content": [
+                    {"type": "text", "text": "See "},
+                    {"type": "reference", "text": "TS 23.502", "ts_no": "23.502", "target": null},
+                    {"type": "text", "text": "."}
+                ]
+            }],
+            "clauses": []
+        })
+    );
+}
@@ -0,0 +1,615 @@
+use crate::html_text;
+use crate::html_tokens::{self, Token};
+use crate::spec_metadata::{self, SpecMetadata};
+use crate::toc::{self, TocEntry};
+use crate::zip_archive::ZipArchive;
+
+use failure::ResultExt;
+use regex::Regex;
+use std::sync::OnceLock;
+
+const VOID_TAGS: [&str; 8] = ["area", "br", "col", "hr", "img", "input", "meta", "wbr"];
+// Tags removed with their content
+const SKIPPED_TAGS: [&str; 4] = ["script", "style", "title", "button"];
+// Presentational tags removed, their content is kept
+const UNWRAPPED_TAGS: [&str; 4] = ["font", "center", "sdfield", "nav"];
+// An open tag of these is closed by the next one of the same name
+const IMPLIED_END_TAGS: [&str; 7] = ["p", "li", "dt", "dd", "tr", "td", "th"];
+// Blocks which cannot be in a paragraph, they close it, with the headings
+const PARAGRAPH_CLOSING_TAGS: [&str; 7] = ["table", "ul", "ol", "dl", "div", "pre", "blockquote"];
+// Tags an implied end does not look past
+const SCOPE_TAGS: [&str; 6] = ["table", "ul", "ol", "dl", "div", "blockquote"];
+const KEPT_ATTRIBUTES: [&str; 10] = [
+    "id", "class", "href", "src", "alt", "title", "colspan", "rowspan", "width", "height",
+];
+
+const SPEC_PAGE_URL: &str = "https://www.3gpp.org/DynaReport/";
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles>
+<rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+</rootfiles>
+</container>
+"#;
+
+const STYLE: &str = r#"body { font-family: sans-serif; line-height: 1.4; }
+table { border-collapse: collapse; max-width: 100%; }
+td, th { border: 1px solid #999999; padding: 0.2em 0.4em; vertical-align: top; }
+img { max-width: 100%; }
+pre { white-space: pre-wrap; font-size: 0.85em; }
+.hl-comment { color: #6a737d; font-style: italic; }
+.hl-string { color: #22863a; }
+.hl-key { color: #005cc5; }
+.hl-keyword { color: #a626a4; font-weight: bold; }
+.hl-number { color: #b35900; }
+abbr.term a { color: inherit; text-decoration: underline dotted; }
+"#;
+
+// An enriched document and the directory its images are in
+pub struct EpubDocument {
+    pub metadata: SpecMetadata,
+    pub content: String,
+    pub base_dir: std::path::PathBuf,
+}
+
+struct ManifestItem {
+    id: String,
+    href: String,
+    media_type: &'static str,
+    properties: Option<&'static str>,
+}
+
+// Packages documents as one EPUB 3 book: a page per document with its images, a navigation
+// document built from the clause headings. Links between the documents of the book point to
+// their pages, links to other specs point to the 3GPP website and other links are removed.
+pub fn save_epub(path: &std::path::Path, documents: &[EpubDocument]) -> Result<(), failure::Error> {
+    println!("\tWriting EPUB {}...", path.to_string_lossy());
+
+    let book_ts_nos = documents
+        .iter()
+        .map(|document| document.metadata.ts_no.as_str())
+        .collect::<Vec<_>>();
+
+    let mut archive = ZipArchive::new();
+    // The mimetype comes first and uncompressed, so the file type can be detected
+    archive.add_file("mimetype", b"application/epub+zip", false)?;
+    archive.add_file("META-INF/container.xml", CONTAINER.as_bytes(), true)?;
+    archive.add_file("OEBPS/style.css", STYLE.as_bytes(), true)?;
+
+    let mut manifest = vec![
+        ManifestItem {
+            id: String::from("nav"),
+            href: String::from("nav.xhtml"),
+            media_type: "application/xhtml+xml",
+            properties: Some("nav"),
+        },
+        ManifestItem {
+            id: String::from("style"),
+            href: String::from("style.css"),
+            media_type: "text/css",
+            properties: None,
+        },
+    ];
+    let mut spine = Vec::new();
+    let mut nav_documents = Vec::new();
+
+    for (i, document) in documents.iter().enumerate() {
+        let ts_no = &document.metadata.ts_no;
+        let image_exists = |src: &str| document.base_dir.join(src).is_file();
+        let (body, images) =
+            convert_document(&document.content, ts_no, &book_ts_nos, &image_exists);
+
+        let href = format!("{}.xhtml", ts_no);
+        let title = spec_metadata::page_title(&document.metadata);
+        archive.add_file(
+            &format!("OEBPS/{}", href),
+            render_xhtml(&title, &body).as_bytes(),
+            true,
+        )?;
+
+        for (j, image) in images.iter().enumerate() {
+            let image_path = document.base_dir.join(image);
+            let content = std::fs::read(&image_path).with_context(|_| {
+                format!("could not read image `{}`", image_path.to_string_lossy())
+            })?;
+            let image_href = format!("{}/{}", ts_no, image);
+            archive.add_file(&format!("OEBPS/{}", image_href), &content, true)?;
+            manifest.push(ManifestItem {
+                id: format!("img-{}-{}", i + 1, j + 1),
+                href: image_href,
+                media_type: image_media_type(image).unwrap(),
+                properties: None,
+            });
+        }
+
+        let id = format!("doc-{}", i + 1);
+        spine.push(id.clone());
+        manifest.push(ManifestItem {
+            id,
+            href: href.clone(),
+            media_type: "application/xhtml+xml",
+            properties: None,
+        });
+        nav_documents.push((href, title, toc::collect_toc(&body)));
+    }
+
+    archive.add_file(
+        "OEBPS/nav.xhtml",
+        render_xhtml("Contents", &render_nav(&nav_documents)).as_bytes(),
+        true,
+    )?;
+    archive.add_file(
+        "OEBPS/content.opf",
+        render_package(documents, &manifest, &spine).as_bytes(),
+        true,
+    )?;
+
+    let data = archive
+        .finish()
+        .with_context(|_| format!("could not package EPUB file `{}`", path.to_string_lossy()))?;
+    std::fs::write(path, data)
+        .with_context(|_| format!("could not write EPUB file `{}`", path.to_string_lossy()))?;
+
+    Ok(())
+}
+
+// Body of a document as XHTML and the images it shows, relative to its directory
+fn convert_document(
+    content: &str,
+    ts_no: &str,
+    book_ts_nos: &[&str],
+    image_exists: &dyn Fn(&str) -> bool,
+) -> (String, Vec<String>) {
+    let body_re = Regex::new(r#"(?is)<body[^>]*>(?P<body>.*)</body>"#).unwrap();
+    let reference_controls_re =
+        Regex::new(r#"(?s)<span class="reference-controls">.*?</span>"#).unwrap();
+
+    let body = body_re
+        .captures(content)
+        .map(|cap| String::from(&cap["body"]))
+        .unwrap_or_else(|| String::from(content));
+    let body = reference_controls_re.replace_all(&body, "");
+
+    let mut writer = XhtmlWriter {
+        ts_no,
+        book_ts_nos,
+        image_exists,
+        result: String::new(),
+        open_tags: Vec::new(),
+        skipped: None,
+        images: Vec::new(),
+    };
+    writer.write(&body);
+    writer.finish()
+}
+
+// Rewrites LibreOffice HTML as well-formed XML: every tag closed, attributes quoted, obsolete
+// markup removed
+struct XhtmlWriter<'a> {
+    ts_no: &'a str,
+    book_ts_nos: &'a [&'a str],
+    image_exists: &'a dyn Fn(&str) -> bool,
+    result: String,
+    open_tags: Vec<String>,
+    skipped: Option<String>,
+    images: Vec<String>,
+}
+
+impl<'a> XhtmlWriter<'a> {
+    fn write(&mut self, html: &str) {
+        for (_, token) in html_tokens::tokenize(html) {
+            match token {
+                Token::Comment => {}
+                Token::Text(text) => {
+                    if self.skipped.is_none() {
+                        self.result
+                            .push_str(&html_text::escape(&html_text::unescape(text)));
+                    }
+                }
+                Token::Open { name, attrs } => {
+                    if self.skipped.is_none() {
+                        self.open_tag(&name, attrs);
+                    }
+                }
+                Token::Close { name } => match &self.skipped {
+                    Some(skipped) if *skipped == name => self.skipped = None,
+                    Some(_) => {}
+                    None => self.close_tag(&name),
+                },
+            }
+        }
+    }
+
+    fn finish(mut self) -> (String, Vec<String>) {
+        while let Some(name) = self.open_tags.pop() {
+            self.result.push_str(&format!("</{}>", name));
+        }
+        (self.result, self.images)
+    }
+
+    fn open_tag(&mut self, name: &str, attrs: &str) {
+        if SKIPPED_TAGS.contains(&name) {
+            if !attrs.trim_end().ends_with('/') {
+                self.skipped = Some(String::from(name));
+            }
+            return;
+        }
+        if UNWRAPPED_TAGS.contains(&name) {
+            return;
+        }
+
+        if IMPLIED_END_TAGS.contains(&name) {
+            self.close_implied(name);
+        }
+        if PARAGRAPH_CLOSING_TAGS.contains(&name) || html_tokens::heading_level(name).is_some() {
+            self.close_implied("p");
+        }
+
+        let attributes = match self.convert_attributes(name, attrs) {
+            Some(attributes) => attributes,
+            None => return,
+        };
+
+        if VOID_TAGS.contains(&name) {
+            self.result.push_str(&format!("<{}{}/>", name, attributes));
+        } else {
+            self.result.push_str(&format!("<{}{}>", name, attributes));
+            self.open_tags.push(String::from(name));
+        }
+    }
+
+    // A closing tag without an opening one is dropped, the tags opened after it are closed
+    fn close_tag(&mut self, name: &str) {
+        if let Some(position) = self.open_tags.iter().rposition(|tag| tag == name) {
+            self.close_from(position);
+        }
+    }
+
+    fn close_implied(&mut self, name: &str) {
+        let open = self
+            .open_tags
+            .iter()
+            .rposition(|tag| tag == name || SCOPE_TAGS.contains(&tag.as_str()));
+        if let Some(position) = open.filter(|&position| self.open_tags[position] == name) {
+            self.close_from(position);
+        }
+    }
+
+    fn close_from(&mut self, position: usize) {
+        for name in self.open_tags.drain(position..).rev() {
+            self.result.push_str(&format!("</{}>", name));
+        }
+    }
+
+    // Kept attributes in XML syntax, none when the whole tag is dropped: images the book does
+    // not contain
+    fn convert_attributes(&mut self, name: &str, attrs: &str) -> Option<String> {
+        let mut attributes: Vec<(String, String)> = Vec::new();
+        for (mut attribute_name, value) in html_tokens::attributes(attrs) {
+            let value = value
+                .map(html_text::unescape)
+                .unwrap_or_else(|| attribute_name.clone());
+
+            // Anchors of LibreOffice, "name" is obsolete
+            if name == "a" && attribute_name == "name" {
+                attribute_name = String::from("id");
+            }
+            let kept = KEPT_ATTRIBUTES.contains(&attribute_name.as_str())
+                && (name == "img" || !["width", "height"].contains(&attribute_name.as_str()));
+            if kept && attributes.iter().all(|(n, _)| *n != attribute_name) {
+                attributes.push((attribute_name, value));
+            }
+        }
+
+        let mut result = String::new();
+        for (attribute_name, value) in attributes {
+            let value = match attribute_name.as_str() {
+                "href" => match self.book_href(&value) {
+                    Some(href) => href,
+                    None => continue,
+                },
+                "src" if name == "img" => {
+                    if !is_local_image(&value) || !(self.image_exists)(&value) {
+                        return None;
+                    }
+                    if !self.images.contains(&value) {
+                        self.images.push(value.clone());
+                    }
+                    format!("{}/{}", self.ts_no, value)
+                }
+                _ => value,
+            };
+            result.push_str(&format!(
+                " {}=\"{}\"",
+                attribute_name,
+                html_text::escape(&value)
+            ));
+        }
+
+        if name == "img" && !result.contains(" alt=\"") {
+            result.push_str(" alt=\"\"");
+        }
+        Some(result)
+    }
+
+    // "../23.502/23.502.html#4.3" becomes "23.502.xhtml#4.3" when TS 23.502 is in the book, else
+    // the page of TS 23.502 on the 3GPP website
+    fn book_href(&self, href: &str) -> Option<String> {
+        static DOCUMENT_RE: OnceLock<Regex> = OnceLock::new();
+        static EXTERNAL_RE: OnceLock<Regex> = OnceLock::new();
+        let document_re = DOCUMENT_RE.get_or_init(|| {
+            Regex::new(r#"^\.\./(?P<ts_no>[^/]+)/(?P<file>[^/#]+)\.html(?P<rest>#.*)?$"#).unwrap()
+        });
+        let external_re =
+            EXTERNAL_RE.get_or_init(|| Regex::new(r#"^[a-zA-Z][a-zA-Z0-9+.-]*:"#).unwrap());
+
+        if href.starts_with('#') || external_re.is_match(href) {
+            return Some(String::from(href));
+        }
+
+        let cap = document_re.captures(href)?;
+        let ts_no = &cap["ts_no"];
+        if cap["file"] != *ts_no {
+            return None;
+        }
+        if !self.book_ts_nos.contains(&ts_no) {
+            return spec_page_url(ts_no);
+        }
+        Some(format!(
+            "{}.xhtml{}",
+            ts_no,
+            cap.name("rest").map_or("", |rest| rest.as_str())
+        ))
+    }
+}
+
+// "23.502" has its versions listed at https://www.3gpp.org/DynaReport/23502.htm
+fn spec_page_url(ts_no: &str) -> Option<String> {
+    let (series, number) = ts_no.split_once('.')?;
+    let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    if !is_number(series) || !is_number(number) {
+        return None;
+    }
+    Some(format!("{}{}{}.htm", SPEC_PAGE_URL, series, number))
+}
+
+fn is_local_image(src: &str) -> bool {
+    !src.contains(':')
+        && !src.starts_with('/')
+        && !src.split('/').any(|part| part == "..")
+        && image_media_type(src).is_some()
+}
+
+// Core media types of EPUB 3.2, other images (WMF, EMF, WebP) may not be shown by readers
+fn image_media_type(path: &str) -> Option<&'static str> {
+    let extension = path.rsplit('.').next()?.to_lowercase();
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "svg" => Some("image/svg+xml"),
+        _ => None,
+    }
+}
+
+fn render_xhtml(title: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" lang="en" xml:lang="en">
+<head>
+<meta charset="utf-8"/>
+<title>{}</title>
+<link rel="stylesheet" type="text/css" href="style.css"/>
+</head>
+<body>
+{}
+</body>
+</html>
+"#,
+        html_text::escape(title),
+        body
+    )
+}
+
+// The clauses of a single document are the top entries, several documents are listed with
+// their clauses nested
+fn render_nav(documents: &[(String, String, Vec<TocEntry>)]) -> String {
+    let list = match documents {
+        [(href, _, entries)] if !entries.is_empty() => render_nav_list(entries, href),
+        _ => {
+            let items = documents
+                .iter()
+                .map(|(href, title, entries)| {
+                    format!(
+                        "<li><a href=\"{}\">{}</a>{}</li>",
+                        html_text::escape(href),
+                        html_text::escape(title),
+                        render_nav_list(entries, href)
+                    )
+                })
+                .collect::<String>();
+            format!("<ol>{}</ol>", items)
+        }
+    };
+
+    format!(
+        "<nav epub:type=\"toc\" id=\"toc\"><h1>Contents</h1>{}</nav>",
+        list
+    )
+}
+
+fn render_nav_list(entries: &[TocEntry], href: &str) -> String {
+    if entries.is_empty() {
+        return String::new();
+    }
+
+    let items = entries
+        .iter()
+        .map(|entry| {
+            format!(
+                "<li><a href=\"{}\">{}</a>{}</li>",
+                html_text::escape(&format!("{}#{}", href, entry.clause)),
+                html_text::escape(&entry.title),
+                render_nav_list(&entry.children, href)
+            )
+        })
+        .collect::<String>();
+    format!("<ol>{}</ol>", items)
+}
+
+fn render_package(
+    documents: &[EpubDocument],
+    manifest: &[ManifestItem],
+    spine: &[String],
+) -> String {
+    let (identifier, title) = match documents {
+        [document] => (
+            format!(
+                "urn:3gpp:ts:{}:{}",
+                document.metadata.ts_no, document.metadata.version
+            ),
+            spec_metadata::page_title(&document.metadata),
+        ),
+        _ => {
+            let ts_nos = documents
+                .iter()
+                .map(|document| document.metadata.ts_no.as_str())
+                .collect::<Vec<_>>();
+            (
+                format!("urn:3gpp:ts:{}", ts_nos.join(",")),
+                format!("TS {}", ts_nos.join(", TS ")),
+            )
+        }
+    };
+    // The dates of the documents keep the book reproducible
+    let modified = documents
+        .iter()
+        .map(|document| document.metadata.date.as_str())
+        .filter(|date| date.len() == 7)
+        .max()
+        .map(|date| format!("{}-01T00:00:00Z", date))
+        .unwrap_or_else(|| String::from("1980-01-01T00:00:00Z"));
+
+    let manifest = manifest
+        .iter()
+        .map(|item| {
+            format!(
+                "<item id=\"{}\" href=\"{}\" media-type=\"{}\"{}/>\n",
+                item.id,
+                html_text::escape(&item.href),
+                item.media_type,
+                item.properties
+                    .map(|properties| format!(" properties=\"{}\"", properties))
+                    .unwrap_or_default()
+            )
+        })
+        .collect::<String>();
+    let spine = spine
+        .iter()
+        .map(|id| format!("<itemref idref=\"{}\"/>\n", id))
+        .collect::<String>();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="uid">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+<dc:identifier id="uid">{}</dc:identifier>
+<dc:title>{}</dc:title>
+<dc:language>en</dc:language>
+<dc:publisher>3GPP</dc:publisher>
+<meta property="dcterms:modified">{}</meta>
+</metadata>
+<manifest>
+{}</manifest>
+<spine>
+{}</spine>
+</package>
+"#,
+        html_text::escape(&identifier),
+        html_text::escape(&title),
+        modified,
+        manifest,
+        spine
+    )
+}
+
+#[test]
+fn test_convert_document() {
+    let source = r##"<html><head><title>Foo</title></head><body lang="en-US">
+<h1 id="4"  class="western">4	General<span class="reference-controls"><a class="permalink" href="#4"></a><button class="copy-reference" type="button"></button></span></h1>
+<p class=western align=left><a name="_Toc1"></a><font face="Arial">A &amp; B&nbsp;<br>
+see <a href="#4">clause 4</a>, <a href="../23.502/23.502.html#4.3">TS 23.502</a> and <a href="../38.331/38.331.html#5">TS 38.331</a>.
+<p>Next</p>
+<p><img src="foo_html_1.png" name="Image1" align=bottom width=100 height=50 border=0><img src="foo_html_2.wmf"><img src="foo_html_3.webp"><img src="missing.png"></p>
+<p>Before<table><tr><td>A<td>B</tr></table>
+<script>var x = "<p>";</script>
+</body></html>"##;
+    let expected = r##"
+<h1 id="4" class="western">4	General</h1>
+<p class="western"><a id="_Toc1"></a>A &amp; B <br/>
+see <a href="#4">clause 4</a>, <a href="23.502.xhtml#4.3">TS 23.502</a> and <a href="https://www.3gpp.org/DynaReport/38331.htm">TS 38.331</a>.
+</p><p>Next</p>
+<p><img src="23.501/foo_html_1.png" width="100" height="50" alt=""/></p>
+<p>Before</p><table><tr><td>A</td><td>B</td></tr></table>
+
+"##;
+
+    let image_exists = |src: &str| src != "missing.png";
+    let (body, images) = convert_document(source, "23.501", &["23.501", "23.502"], &image_exists);
+    assert_eq!(body, expected);
+    assert_eq!(images, vec![String::from("foo_html_1.png")]);
+}
+
+#[test]
+fn test_convert_document_annex_heading_closes_paragraph() {
+    let source = "<p>Before\n<h8 id=\"A.1\">A.1\tFoo</h8>\n<p>After";
+    let expected = "<p>Before\n</p><h8 id=\"A.1\">A.1\tFoo</h8>\n<p>After</p>";
+
+    let (body, _) = convert_document(source, "23.501", &["23.501"], &|_: &str| true);
+    assert_eq!(body, expected);
+}
+
+#[test]
+fn test_render_nav() {
+    let entries = || {
+        vec![TocEntry {
+            clause: String::from("4"),
+            title: String::from("4 General"),
+            level: 1,
+            children: vec![TocEntry {
+                clause: String::from("4.1"),
+                title: String::from("4.1 Foo & bar"),
+                level: 2,
+                children: Vec::new(),
+            }],
+        }]
+    };
+
+    let single = vec![(
+        String::from("23.501.xhtml"),
+        String::from("TS 23.501"),
+        entries(),
+    )];
+    assert_eq!(
+        render_nav(&single),
+        r##"<nav epub:type="toc" id="toc"><h1>Contents</h1><ol><li><a href="23.501.xhtml#4">4 General</a><ol><li><a href="23.501.xhtml#4.1">4.1 Foo &amp; bar</a></li></ol></li></ol></nav>"##
+    );
+
+    let several = vec![
+        (
+            String::from("23.501.xhtml"),
+            String::from("TS 23.501"),
+            Vec::new(),
+        ),
+        (
+            String::from("23.502.xhtml"),
+            String::from("TS 23.502"),
+            entries(),
+        ),
+    ];
+    assert!(render_nav(&several).starts_with(
+        r##"<nav epub:type="toc" id="toc"><h1>Contents</h1><ol><li><a href="23.501.xhtml">TS 23.501</a></li><li><a href="23.502.xhtml">TS 23.502</a><ol><li><a href="23.502.xhtml#4">"##
+    ));
+}
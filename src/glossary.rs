@@ -0,0 +1,480 @@
+use crate::html_text;
+use crate::linkable_text;
+
+use failure::ResultExt;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+pub const GLOSSARY_FILE_NAME: &str = "glossary.json";
+
+// TR 21.905, the vocabulary most specs import their terms from
+pub const VOCABULARY_TS_NO: &str = "21.905";
+
+// Longer definitions are cut in tooltips, the link leads to the whole text
+const MAX_TOOLTIP_LEN: usize = 300;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TermKind {
+    Definition,
+    Abbreviation,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GlossaryEntry {
+    pub term: String,
+    pub kind: TermKind,
+    pub definition: String,
+}
+
+// Terms and abbreviations of clause 3 of a document, stored next to its HTML file
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Glossary {
+    pub ts_no: String,
+    // Clause 3 says the terms of TR 21.905 apply too
+    pub imports_vocabulary: bool,
+    pub entries: Vec<GlossaryEntry>,
+}
+
+// A term the tooltip pass annotates, with the link to its definition
+#[derive(Debug, PartialEq)]
+pub struct DefinedTerm {
+    pub term: String,
+    pub tooltip: String,
+    pub href: String,
+}
+
+// A definition or abbreviation paragraph of clause 3, found at `start` in the source
+struct EntryParagraph {
+    start: usize,
+    entry: GlossaryEntry,
+}
+
+pub fn extract_glossary(content: &str, ts_no: &str) -> Glossary {
+    println!("\tExtracting glossary...");
+
+    let (paragraphs, imports_vocabulary) = find_entry_paragraphs(content);
+    let mut entries: Vec<GlossaryEntry> = Vec::new();
+    for paragraph in paragraphs {
+        if !entries
+            .iter()
+            .any(|e| e.term == paragraph.entry.term && e.kind == paragraph.entry.kind)
+        {
+            entries.push(paragraph.entry);
+        }
+    }
+
+    Glossary {
+        ts_no: String::from(ts_no),
+        imports_vocabulary: imports_vocabulary && ts_no != VOCABULARY_TS_NO,
+        entries,
+    }
+}
+
+// Walks the headings and paragraphs of clause 3. Definitions are "<b>term:</b> text" paragraphs
+// of the definitions clauses, abbreviations are "ABBR<tab>expansion" paragraphs of the
+// abbreviations clauses. Clause 3 may also be a single "Definitions and abbreviations" clause.
+fn find_entry_paragraphs(content: &str) -> (Vec<EntryParagraph>, bool) {
+    let block_re = Regex::new(r#"(?is)<h\d[\s>].*?</h\d>|<p[\s>].*?</p>"#).unwrap();
+    let clause_3_re = Regex::new(r#"^3(\.\d+)*\s+(?P<title>.+)$"#).unwrap();
+
+    let mut paragraphs = Vec::new();
+    let mut imports_vocabulary = false;
+    let mut in_clause_3 = false;
+    let mut has_definitions = false;
+    let mut has_abbreviations = false;
+
+    for block in block_re.find_iter(content) {
+        let html = block.as_str();
+        if html[1..].starts_with(['h', 'H']) {
+            let text = html_text::to_plain_text(html);
+            let title = clause_3_re
+                .captures(&text)
+                .map(|cap| cap["title"].to_lowercase());
+            in_clause_3 = title.is_some();
+            let title = title.unwrap_or_default();
+            has_definitions = title.contains("definition") || title.contains("terms");
+            has_abbreviations = title.contains("abbreviation");
+            continue;
+        }
+        if !in_clause_3 {
+            continue;
+        }
+
+        if html.contains(VOCABULARY_TS_NO) {
+            imports_vocabulary = true;
+        }
+
+        let entry = if has_definitions {
+            parse_definition(html)
+        } else {
+            None
+        };
+        let entry = entry.or_else(|| {
+            if has_abbreviations {
+                parse_abbreviation(html)
+            } else {
+                None
+            }
+        });
+        if let Some(entry) = entry {
+            paragraphs.push(EntryParagraph {
+                start: block.start(),
+                entry,
+            });
+        }
+    }
+
+    (paragraphs, imports_vocabulary)
+}
+
+fn paragraph_inner_html(paragraph: &str) -> &str {
+    let start = paragraph.find('>').map_or(0, |end| end + 1);
+    let end = paragraph.len() - "</p>".len();
+    if start <= end {
+        &paragraph[start..end]
+    } else {
+        ""
+    }
+}
+
+// "<b>PDU Session:</b> Association between..." or "<b>PDU Session</b>: Association between..."
+fn parse_definition(paragraph: &str) -> Option<GlossaryEntry> {
+    let definition_re =
+        Regex::new(r#"(?is)^\s*(<a\s[^>]*>\s*</a>\s*)?<b>(?P<term>.*?)</b>(?P<text>.*)$"#).unwrap();
+
+    let cap = definition_re.captures(paragraph_inner_html(paragraph))?;
+    let term = html_text::to_plain_text(&cap["term"]);
+    let text = html_text::to_plain_text(&cap["text"]);
+    let (term, text) = match term.strip_suffix(':') {
+        Some(term) => (term.trim(), text.as_str()),
+        None => (term.as_str(), text.strip_prefix(':')?.trim_start()),
+    };
+
+    if term.is_empty() || term.len() > 100 || text.is_empty() {
+        return None;
+    }
+
+    Some(GlossaryEntry {
+        term: String::from(term),
+        kind: TermKind::Definition,
+        definition: String::from(text),
+    })
+}
+
+// "AMF<tab>Access and Mobility Management Function", LibreOffice keeps the tab in the text
+fn parse_abbreviation(paragraph: &str) -> Option<GlossaryEntry> {
+    let tag_re = Regex::new(r#"(?s)<[^<>]+?>"#).unwrap();
+
+    let html = paragraph_inner_html(paragraph)
+        .replace("\r\n", " ")
+        .replace('\n', " ");
+    let text = html_text::unescape(&tag_re.replace_all(&html, ""));
+    let (term, expansion) = text.trim().split_once('\t')?;
+    let term = term.trim();
+    let expansion = expansion.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if term.is_empty() || term.len() > 20 || term.contains(char::is_whitespace) {
+        return None;
+    }
+    if expansion.is_empty() {
+        return None;
+    }
+
+    Some(GlossaryEntry {
+        term: String::from(term),
+        kind: TermKind::Abbreviation,
+        definition: expansion,
+    })
+}
+
+// "PDU Session" gives "term-PDU-Session"
+pub fn term_id(term: &str) -> String {
+    let slug = term
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect::<String>();
+    format!("term-{}", slug.trim_matches('-'))
+}
+
+// Puts an id on the definition and abbreviation paragraphs, so tooltips can link to them
+pub fn add_term_anchors(content: &str) -> String {
+    println!("\tAdding term anchors...");
+
+    let (paragraphs, _) = find_entry_paragraphs(content);
+    let mut used_ids = HashSet::new();
+
+    let mut result = String::new();
+    let mut copied_until = 0;
+    for paragraph in paragraphs {
+        let id = term_id(&paragraph.entry.term);
+        let tag_end = paragraph.start + content[paragraph.start..].find('>').unwrap();
+        if content[paragraph.start..tag_end].contains(" id=") || !used_ids.insert(id.clone()) {
+            continue;
+        }
+
+        let name_end = paragraph.start + "<p".len();
+        result.push_str(&content[copied_until..name_end]);
+        result.push_str(&format!(" id=\"{}\"", id));
+        copied_until = name_end;
+    }
+    result.push_str(&content[copied_until..]);
+
+    result
+}
+
+// Terms of a document followed by the ones it imports from TR 21.905. Its own definitions take
+// precedence over the vocabulary.
+pub fn defined_terms(glossary: &Glossary, vocabulary: Option<&Glossary>) -> Vec<DefinedTerm> {
+    let mut terms: Vec<DefinedTerm> = Vec::new();
+    let mut push_entries = |entries: &[GlossaryEntry], href_prefix: &str| {
+        for entry in entries {
+            if terms.iter().all(|t| t.term != entry.term) {
+                terms.push(DefinedTerm {
+                    term: entry.term.clone(),
+                    tooltip: tooltip_text(&entry.definition),
+                    href: format!("{}#{}", href_prefix, term_id(&entry.term)),
+                });
+            }
+        }
+    };
+
+    push_entries(&glossary.entries, "");
+    if let Some(vocabulary) = vocabulary {
+        if glossary.imports_vocabulary && vocabulary.ts_no != glossary.ts_no {
+            push_entries(
+                &vocabulary.entries,
+                &format!("../{0}/{0}.html", vocabulary.ts_no),
+            );
+        }
+    }
+
+    terms
+}
+
+fn tooltip_text(definition: &str) -> String {
+    if definition.chars().count() <= MAX_TOOLTIP_LEN {
+        return String::from(definition);
+    }
+
+    let cut = definition.chars().take(MAX_TOOLTIP_LEN).collect::<String>();
+    format!("{}…", cut.trim_end())
+}
+
+// Wraps the first occurrence of every defined term in a clause in an <abbr> with the definition
+// as tooltip, linking to the definition. Clause 3 itself and the text before the first clause
+// (cover page, foreword) are left alone.
+pub fn add_term_tooltips(content: &str, terms: &[DefinedTerm]) -> String {
+    println!("\tAdding term tooltips...");
+
+    // Single letters are too often ordinary words or variables
+    let mut terms = terms
+        .iter()
+        .filter(|t| t.term.chars().count() >= 2)
+        .collect::<Vec<_>>();
+    if terms.is_empty() {
+        return String::from(content);
+    }
+
+    // The text is HTML, so terms are matched escaped. Longest terms first, so "PDU Session ID"
+    // wins over "PDU Session".
+    terms.sort_by_key(|t| std::cmp::Reverse(t.term.len()));
+    let by_term = terms
+        .iter()
+        .map(|t| (html_text::escape(&t.term), *t))
+        .collect::<HashMap<_, _>>();
+    let term_re = Regex::new(
+        &terms
+            .iter()
+            .map(|t| regex::escape(&html_text::escape(&t.term)))
+            .collect::<Vec<_>>()
+            .join("|"),
+    )
+    .unwrap();
+
+    let is_word_char = |c: char| c.is_alphanumeric() || c == '-' || c == '_';
+    let mut annotated_clause: Option<String> = None;
+    let mut annotated_terms: HashSet<String> = HashSet::new();
+
+    linkable_text::replace_linkable_text(content, |text, clause| {
+        let clause = match clause {
+            Some(clause) if clause != "3" && !clause.starts_with("3.") => clause,
+            _ => return String::from(text),
+        };
+        if annotated_clause.as_deref() != Some(clause) {
+            annotated_clause = Some(String::from(clause));
+            annotated_terms.clear();
+        }
+
+        let mut result = String::new();
+        let mut copied_until = 0;
+        for found in term_re.find_iter(text) {
+            let before = text[..found.start()].chars().next_back();
+            let after = text[found.end()..].chars().next();
+            if before.is_some_and(is_word_char) || after.is_some_and(is_word_char) {
+                continue;
+            }
+            if !annotated_terms.insert(String::from(found.as_str())) {
+                continue;
+            }
+
+            let term = by_term[found.as_str()];
+            result.push_str(&text[copied_until..found.start()]);
+            result.push_str(&format!(
+                "<abbr class=\"term\" title=\"{}\"><a href=\"{}\">{}</a></abbr>",
+                html_text::escape(&term.tooltip),
+                term.href,
+                found.as_str()
+            ));
+            copied_until = found.end();
+        }
+        result.push_str(&text[copied_until..]);
+
+        result
+    })
+}
+
+pub fn save_glossary(output_dir: &str, glossary: &Glossary) -> Result<(), failure::Error> {
+    let path = format!("{}/{}", output_dir, GLOSSARY_FILE_NAME);
+    let content = serde_json::to_string_pretty(glossary)?;
+    std::fs::write(&path, content)
+        .with_context(|_| format!("could not write glossary file `{}`", path))?;
+
+    Ok(())
+}
+
+// Glossary of a document in the output directory, none if it was converted without glossary
+pub fn load_glossary(dir: &std::path::Path) -> Result<Option<Glossary>, failure::Error> {
+    let path = dir.join(GLOSSARY_FILE_NAME);
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let content = std::fs::read_to_string(&path)
+        .with_context(|_| format!("could not read glossary file `{}`", path.display()))?;
+    let glossary = serde_json::from_str(&content)
+        .with_context(|_| format!("could not parse glossary file `{}`", path.display()))?;
+
+    Ok(Some(glossary))
+}
+
+// Glossaries of every document in the output directory, also of the ones converted by previous
+// runs
+pub fn load_all_glossaries(out_path: &std::path::Path) -> Result<Vec<Glossary>, failure::Error> {
+    let mut glossaries = Vec::new();
+
+    for entry in std::fs::read_dir(out_path)
+        .with_context(|_| format!("could not read output directory `{}`", out_path.display()))?
+    {
+        if let Some(glossary) = load_glossary(&entry?.path())? {
+            glossaries.push(glossary);
+        }
+    }
+
+    glossaries.sort_by(|a, b| a.ts_no.cmp(&b.ts_no));
+    Ok(glossaries)
+}
+
+#[cfg(test)]
+const TEST_CLAUSE_3: &str = "<h1 class=\"western\"><a name=\"_Toc1\"></a>3\tDefinitions of terms and abbreviations</h1>
+<h2 class=\"western\">3.1\tTerms</h2>
+<p class=\"western\">For the purposes of the present document, the terms given in TR 21.905 [1] and the following apply.</p>
+<p class=\"western\"><b>PDU Session:</b> Association between the UE and a Data Network.</p>
+<p class=\"western\"><b>Network Slice</b>: A logical network.</p>
+<h2 class=\"western\">3.2\tAbbreviations</h2>
+<p class=\"EW-western\">AMF\tAccess and Mobility\nManagement Function</p>
+<p class=\"EW-western\">SMF\tSession Management Function</p>
+<h1 class=\"western\">4\tArchitecture</h1>
+<p class=\"western\"><b>Note:</b> not a definition.</p>";
+
+#[test]
+fn test_extract_glossary() {
+    let glossary = extract_glossary(TEST_CLAUSE_3, "23.501");
+
+    assert!(glossary.imports_vocabulary);
+    assert_eq!(
+        glossary
+            .entries
+            .iter()
+            .map(|e| (e.term.as_str(), e.kind, e.definition.as_str()))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                "PDU Session",
+                TermKind::Definition,
+                "Association between the UE and a Data Network."
+            ),
+            ("Network Slice", TermKind::Definition, "A logical network."),
+            (
+                "AMF",
+                TermKind::Abbreviation,
+                "Access and Mobility Management Function"
+            ),
+            ("SMF", TermKind::Abbreviation, "Session Management Function"),
+        ]
+    );
+}
+
+#[test]
+fn test_add_term_anchors() {
+    let result = add_term_anchors(TEST_CLAUSE_3);
+
+    assert!(result.contains("<p id=\"term-PDU-Session\" class=\"western\"><b>PDU Session:</b>"));
+    assert!(result.contains("<p id=\"term-AMF\" class=\"EW-western\">AMF\t"));
+    assert!(result.contains("<p class=\"western\"><b>Note:</b>"));
+}
+
+#[test]
+fn test_defined_terms() {
+    let glossary = extract_glossary(TEST_CLAUSE_3, "23.501");
+    let vocabulary = Glossary {
+        ts_no: String::from(VOCABULARY_TS_NO),
+        imports_vocabulary: false,
+        entries: vec![
+            GlossaryEntry {
+                term: String::from("AMF"),
+                kind: TermKind::Abbreviation,
+                definition: String::from("Other"),
+            },
+            GlossaryEntry {
+                term: String::from("UE"),
+                kind: TermKind::Abbreviation,
+                definition: String::from("User Equipment"),
+            },
+        ],
+    };
+
+    let terms = defined_terms(&glossary, Some(&vocabulary));
+    assert_eq!(terms.len(), 5);
+    assert_eq!(terms[2].href, "#term-AMF");
+    assert_eq!(terms[2].tooltip, "Access and Mobility Management Function");
+    assert_eq!(
+        terms[4],
+        DefinedTerm {
+            term: String::from("UE"),
+            tooltip: String::from("User Equipment"),
+            href: String::from("../21.905/21.905.html#term-UE"),
+        }
+    );
+}
+
+#[test]
+fn test_add_term_tooltips() {
+    let terms = vec![
+        DefinedTerm {
+            term: String::from("AMF"),
+            tooltip: String::from("Access & Mobility"),
+            href: String::from("#term-AMF"),
+        },
+        DefinedTerm {
+            term: String::from("PDU Session"),
+            tooltip: String::from("Association"),
+            href: String::from("#term-PDU-Session"),
+        },
+    ];
+    let source = r##"<p>AMF before clauses</p><h2 id="3.2">3.2 Abbreviations</h2><p>AMF</p><h2 id="4.1">4.1 Foo</h2><p>The AMF and AMF-X set up PDU Sessions, see <a href="#5">AMF</a>.</p><p>AMF again</p><h2 id="4.2">4.2 Bar</h2><p>AMF</p>"##;
+    let expected = r##"<p>AMF before clauses</p><h2 id="3.2">3.2 Abbreviations</h2><p>AMF</p><h2 id="4.1">4.1 Foo</h2><p>The <abbr class="term" title="Access &amp; Mobility"><a href="#term-AMF">AMF</a></abbr> and AMF-X set up PDU Sessions, see <a href="#5">AMF</a>.</p><p>AMF again</p><h2 id="4.2">4.2 Bar</h2><p><abbr class="term" title="Access &amp; Mobility"><a href="#term-AMF">AMF</a></abbr></p>"##;
+
+    assert_eq!(add_term_tooltips(source, &terms), expected);
+}
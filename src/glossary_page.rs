@@ -0,0 +1,279 @@
+use crate::glossary;
+use crate::glossary::{Glossary, TermKind};
+use crate::html_text;
+
+use failure::ResultExt;
+use regex::Regex;
+use std::collections::BTreeMap;
+
+pub const GLOSSARY_PAGE_FILE_NAME: &str = "glossary.html";
+
+// One wording of a term and the specs using it, with the term as spelled in each of them
+struct Definition<'a> {
+    text: &'a str,
+    specs: Vec<(&'a str, &'a str)>,
+}
+
+// A term defined by one or more specs, with one definition per distinct wording
+struct GlossaryTerm<'a> {
+    term: &'a str,
+    kind: TermKind,
+    definitions: Vec<Definition<'a>>,
+}
+
+impl GlossaryTerm<'_> {
+    fn is_conflicting(&self) -> bool {
+        self.definitions.len() > 1
+    }
+}
+
+// Writes glossary.html merging the glossaries of every spec found in the output directory
+pub fn generate_glossary_page(out_path: &std::path::Path) -> Result<(), failure::Error> {
+    println!("Generating glossary page...");
+
+    let glossaries = glossary::load_all_glossaries(out_path)?;
+    let terms = merge_glossaries(&glossaries);
+
+    let page_path = out_path.join(GLOSSARY_PAGE_FILE_NAME);
+    std::fs::write(&page_path, render_glossary_page(&terms))
+        .with_context(|_| format!("could not write glossary page `{}`", page_path.display()))?;
+
+    Ok(())
+}
+
+// Definitions differing only in case, spacing, the final period or reference numbers like "[2]"
+// are the same
+fn normalize_definition(text: &str) -> String {
+    let reference_re = Regex::new(r#"\[\d+\]"#).unwrap();
+
+    let text = reference_re.replace_all(text, "");
+    let text = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    String::from(text.trim_end_matches('.').trim_end())
+}
+
+// Groups the entries by term, ignoring case, sorted alphabetically. Definitions and abbreviations
+// of the same term are separate entries.
+fn merge_glossaries(glossaries: &[Glossary]) -> Vec<GlossaryTerm<'_>> {
+    let mut terms: BTreeMap<(String, bool), GlossaryTerm> = BTreeMap::new();
+
+    for glossary in glossaries {
+        for entry in &glossary.entries {
+            let key = (
+                entry.term.to_lowercase(),
+                entry.kind == TermKind::Abbreviation,
+            );
+            let term = terms.entry(key).or_insert_with(|| GlossaryTerm {
+                term: &entry.term,
+                kind: entry.kind,
+                definitions: Vec::new(),
+            });
+
+            let normalized = normalize_definition(&entry.definition);
+            match term
+                .definitions
+                .iter_mut()
+                .find(|d| normalize_definition(d.text) == normalized)
+            {
+                Some(definition) => definition.specs.push((&glossary.ts_no, &entry.term)),
+                None => term.definitions.push(Definition {
+                    text: &entry.definition,
+                    specs: vec![(&glossary.ts_no, &entry.term)],
+                }),
+            }
+        }
+    }
+
+    terms.into_values().collect()
+}
+
+fn render_glossary_page(terms: &[GlossaryTerm]) -> String {
+    let mut letters: BTreeMap<char, Vec<&GlossaryTerm>> = BTreeMap::new();
+    for term in terms {
+        let letter = term
+            .term
+            .chars()
+            .next()
+            .filter(|c| c.is_ascii_alphabetic())
+            .map_or('#', |c| c.to_ascii_uppercase());
+        letters.entry(letter).or_default().push(term);
+    }
+
+    let letter_id = |letter: char| {
+        if letter == '#' {
+            String::from("letter-other")
+        } else {
+            format!("letter-{}", letter)
+        }
+    };
+    let letter_links = letters
+        .keys()
+        .map(|letter| format!("<a href=\"#{}\">{}</a>", letter_id(*letter), letter))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let conflicts = terms.iter().filter(|t| t.is_conflicting()).count();
+
+    let mut body = String::new();
+    for (letter, terms) in &letters {
+        body.push_str(&format!(
+            "<h2 id=\"{}\">{}</h2>\n<table>\n<tr><th>Term</th><th>Definition</th><th>Defined in</th></tr>\n",
+            letter_id(*letter),
+            letter
+        ));
+
+        for term in terms {
+            let class = if term.is_conflicting() {
+                " class=\"conflict\""
+            } else {
+                ""
+            };
+            let kind = match term.kind {
+                TermKind::Abbreviation => " <small>abbreviation</small>",
+                TermKind::Definition => "",
+            };
+            let marker = if term.is_conflicting() {
+                " <span class=\"conflict-marker\" title=\"The specs define this term differently\">conflicting definitions</span>"
+            } else {
+                ""
+            };
+
+            for (i, definition) in term.definitions.iter().enumerate() {
+                body.push_str(&format!("<tr{}>", class));
+                if i == 0 {
+                    body.push_str(&format!(
+                        "<td rowspan=\"{}\"><b>{}</b>{}{}</td>",
+                        term.definitions.len(),
+                        html_text::escape(term.term),
+                        kind,
+                        marker
+                    ));
+                }
+
+                let specs = definition
+                    .specs
+                    .iter()
+                    .map(|(ts_no, spelling)| {
+                        format!(
+                            "<a href=\"{0}/{0}.html#{1}\">TS {0}</a>",
+                            ts_no,
+                            glossary::term_id(spelling)
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                body.push_str(&format!(
+                    "<td>{}</td><td>{}</td></tr>\n",
+                    html_text::escape(definition.text),
+                    specs
+                ));
+            }
+        }
+
+        body.push_str("</table>\n");
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Glossary of 3GPP specifications</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; width: 100%; }}
+th, td {{ border-bottom: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }}
+small {{ color: #666; font-weight: normal; }}
+tr.conflict td {{ background: #fff4e0; }}
+.conflict-marker {{ display: block; color: #b35900; font-size: 0.8em; }}
+</style>
+</head>
+<body>
+<h1>Glossary of 3GPP specifications</h1>
+<p><a href="index.html">All specifications</a></p>
+<p>{} terms, {} with conflicting definitions</p>
+<p>{}</p>
+{}</body>
+</html>
+"#,
+        terms.len(),
+        conflicts,
+        letter_links,
+        body
+    )
+}
+
+#[cfg(test)]
+fn test_glossaries() -> Vec<Glossary> {
+    use crate::glossary::GlossaryEntry;
+
+    let entry = |term: &str, kind: TermKind, definition: &str| GlossaryEntry {
+        term: String::from(term),
+        kind,
+        definition: String::from(definition),
+    };
+    vec![
+        Glossary {
+            ts_no: String::from("23.501"),
+            imports_vocabulary: true,
+            entries: vec![
+                entry(
+                    "PDU Session",
+                    TermKind::Definition,
+                    "Association between the UE and a Data Network [2].",
+                ),
+                entry(
+                    "AMF",
+                    TermKind::Abbreviation,
+                    "Access and Mobility Management Function",
+                ),
+            ],
+        },
+        Glossary {
+            ts_no: String::from("24.501"),
+            imports_vocabulary: true,
+            entries: vec![
+                entry(
+                    "PDU session",
+                    TermKind::Definition,
+                    "association between the UE and a data network",
+                ),
+                entry("AMF", TermKind::Abbreviation, "Access Management Function"),
+                entry("5GMM", TermKind::Abbreviation, "5GS Mobility Management"),
+            ],
+        },
+    ]
+}
+
+#[test]
+fn test_merge_glossaries() {
+    let glossaries = test_glossaries();
+    let terms = merge_glossaries(&glossaries);
+
+    assert_eq!(
+        terms.iter().map(|t| t.term).collect::<Vec<_>>(),
+        vec!["5GMM", "AMF", "PDU Session"]
+    );
+    assert!(!terms[0].is_conflicting());
+    assert!(terms[1].is_conflicting());
+    assert_eq!(terms[1].definitions[1].specs, vec![("24.501", "AMF")]);
+    assert!(!terms[2].is_conflicting());
+    assert_eq!(
+        terms[2].definitions[0].specs,
+        vec![("23.501", "PDU Session"), ("24.501", "PDU session")]
+    );
+}
+
+#[test]
+fn test_render_glossary_page() {
+    let glossaries = test_glossaries();
+    let page = render_glossary_page(&merge_glossaries(&glossaries));
+
+    assert!(page.contains("<p>3 terms, 1 with conflicting definitions</p>"));
+    assert!(page.contains("<p><a href=\"#letter-other\">#</a> <a href=\"#letter-A\">A</a> <a href=\"#letter-P\">P</a></p>"));
+    assert!(page.contains("<tr class=\"conflict\"><td rowspan=\"2\"><b>AMF</b> <small>abbreviation</small> <span class=\"conflict-marker\""));
+    assert!(page.contains("<td>Access Management Function</td><td><a href=\"24.501/24.501.html#term-AMF\">TS 24.501</a></td></tr>"));
+    assert!(page.contains("<a href=\"23.501/23.501.html#term-PDU-Session\">TS 23.501</a>, <a href=\"24.501/24.501.html#term-PDU-session\">TS 24.501</a>"));
+}
@@ -0,0 +1,363 @@
+use crate::code_annexes;
+use crate::html_text;
+use crate::html_tokens::{self, Token};
+
+use regex::{Captures, Regex};
+use std::ops::Range;
+
+// Highlighted pieces of code, in the order of the groups in the token patterns
+const TOKEN_KINDS: [&str; 5] = ["comment", "string", "key", "keyword", "number"];
+
+const ASN1_TOKENS: &str = r#"(?m)(?P<comment>--.*?(--|$))|(?P<string>"[^"]*")|\b(?P<keyword>SEQUENCE|SET|OF|CHOICE|ENUMERATED|INTEGER|BOOLEAN|NULL|BIT|OCTET|STRING|OPTIONAL|DEFAULT|SIZE|CONTAINING|DEFINITIONS|AUTOMATIC|TAGS|BEGIN|END|IMPORTS|FROM|EXPORTS|TRUE|FALSE|WITH|COMPONENTS|CLASS|UNIQUE|IDENTIFIED|BY|OBJECT|IDENTIFIER|PRESENT|ABSENT|MAX|MIN)\b|\b(?P<number>\d+)\b"#;
+const JSON_TOKENS: &str = r#"(?P<key>"(\\.|[^"\\\n])*")\s*:|(?P<string>"(\\.|[^"\\\n])*")|\b(?P<keyword>true|false|null)\b|(?P<number>-?\b\d+(\.\d+)?([eE][+-]?\d+)?)\b"#;
+const YAML_TOKENS: &str = r#"(?m)(?P<comment>(^|[ \t])#.*$)|^[ \t]*(- )?(?P<key>[A-Za-z_$][\w$.-]*):|(?P<string>'[^'\n]*'|"(\\.|[^"\\\n])*")|\b(?P<keyword>true|false|null)\b|\b(?P<number>\d+(\.\d+)*)\b"#;
+const ABNF_TOKENS: &str = r#"(?m)(?P<comment>;.*$)|(?P<string>"[^"\n]*")|^(?P<key>[A-Za-z][A-Za-z0-9-]*)|\b(?P<keyword>ALPHA|DIGIT|HEXDIG|DQUOTE|SP|HTAB|WSP|LWSP|VCHAR|CHAR|OCTET|CTL|CR|LF|CRLF|BIT)\b|(?P<number>%[xdb][0-9A-Fa-f]+([.-][0-9A-Fa-f]+)*|\b\d+\b)"#;
+const YANG_TOKENS: &str = r#"(?s)(?P<comment>//[^\n]*|/\*.*?\*/)|(?P<string>"(\\.|[^"\\])*"|'[^']*')|\b(?P<keyword>module|submodule|namespace|prefix|import|include|revision|description|reference|organization|contact|container|list|leaf-list|leaf|grouping|typedef|uses|type|key|config|mandatory|default|enum|identity|base|augment|when|must|choice|case|feature|if-feature|units|range|length|pattern|yang-version|presence|min-elements|max-elements|ordered-by|status|rpc|input|output|notification|extension|argument|refine)\b|\b(?P<number>\d+)\b"#;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Language {
+    Asn1,
+    Json,
+    Yaml,
+    Abnf,
+    Yang,
+    // Message flows and pseudo-code are not highlighted
+    Text,
+}
+
+impl Language {
+    pub fn name(self) -> &'static str {
+        match self {
+            Language::Asn1 => "asn1",
+            Language::Json => "json",
+            Language::Yaml => "yaml",
+            Language::Abnf => "abnf",
+            Language::Yang => "yang",
+            Language::Text => "text",
+        }
+    }
+
+    fn tokens(self) -> Option<&'static str> {
+        match self {
+            Language::Asn1 => Some(ASN1_TOKENS),
+            Language::Json => Some(JSON_TOKENS),
+            Language::Yaml => Some(YAML_TOKENS),
+            Language::Abnf => Some(ABNF_TOKENS),
+            Language::Yang => Some(YANG_TOKENS),
+            Language::Text => None,
+        }
+    }
+}
+
+// Guesses the language from the whole listing, most specific first
+pub fn detect_language(lines: &[String]) -> Language {
+    let asn1_re =
+        Regex::new(r#"::=\s*(SEQUENCE|CHOICE|ENUMERATED|INTEGER|BIT STRING|OCTET STRING|BOOLEAN)"#)
+            .unwrap();
+    let json_key_re = Regex::new(r#""[^"]*"\s*:"#).unwrap();
+    let yang_re =
+        Regex::new(r#"^\s*(module|submodule|container|leaf|leaf-list|list|grouping|typedef|uses)\s+\S+\s*[{;]"#)
+            .unwrap();
+    let yaml_key_re = Regex::new(r#"^\s*(- )?[A-Za-z_$][\w$.-]*:(\s|$)"#).unwrap();
+    let abnf_rule_re = Regex::new(r#"^[A-Za-z][A-Za-z0-9-]*\s*=/?\s*\S"#).unwrap();
+
+    let code = lines.join("\n");
+    let code_lines = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>();
+    if code_lines.is_empty() {
+        return Language::Text;
+    }
+    // Most lines are of the language, the others are continuation or comment lines
+    let mostly = |re: &Regex| {
+        code_lines.iter().filter(|line| re.is_match(line)).count() * 2 >= code_lines.len()
+    };
+
+    let first_char = code.trim_start().chars().next().unwrap();
+    if code.contains("-- ASN1START") || asn1_re.is_match(&code) {
+        Language::Asn1
+    } else if (first_char == '{' || first_char == '[') && json_key_re.is_match(&code) {
+        Language::Json
+    } else if code_lines.iter().any(|line| yang_re.is_match(line)) && code.contains('{') {
+        Language::Yang
+    } else if mostly(&yaml_key_re) {
+        Language::Yaml
+    } else if code_lines.iter().any(|line| abnf_rule_re.is_match(line))
+        && code_lines.iter().all(|line| {
+            abnf_rule_re.is_match(line)
+                || line.starts_with(char::is_whitespace)
+                || line.trim_start().starts_with(';')
+        })
+    {
+        Language::Abnf
+    } else {
+        Language::Text
+    }
+}
+
+// Escaped code with its comments, strings, keys, keywords and numbers in <span class="hl-...">
+pub fn highlight(code: &str, language: Language) -> String {
+    let tokens = match find_tokens(code, language) {
+        Some(tokens) => tokens,
+        None => return html_text::escape(code),
+    };
+
+    let mut result = String::new();
+    let mut copied_until = 0;
+    for (token, kind) in tokens {
+        result.push_str(&html_text::escape(&code[copied_until..token.start]));
+        result.push_str(&format!(
+            "<span class=\"hl-{}\">{}</span>",
+            kind,
+            html_text::escape(&code[token.clone()])
+        ));
+        copied_until = token.end;
+    }
+    result.push_str(&html_text::escape(&code[copied_until..]));
+
+    result
+}
+
+// Highlighted pieces of the code and their kind, none when the language is not highlighted
+fn find_tokens(code: &str, language: Language) -> Option<Vec<(Range<usize>, &'static str)>> {
+    let token_re = Regex::new(language.tokens()?).unwrap();
+
+    let tokens = token_re
+        .captures_iter(code)
+        .filter_map(|cap| {
+            TOKEN_KINDS.iter().find_map(|kind| {
+                cap.name(kind)
+                    .map(|token| (token.start()..token.end(), *kind))
+            })
+        })
+        .collect();
+    Some(tokens)
+}
+
+enum Event<'a> {
+    SpanOpen(&'static str),
+    SpanClose,
+    // A tag of the rendered code, and whether its element is within the highlighted piece
+    Tag(&'a str, bool),
+}
+
+// Highlights the text of rendered code as a whole, so a token can contain anchors and links.
+// Their tags are put back at their place, a tag whose element is not within a highlighted piece
+// splits it.
+fn highlight_html(html: &str, language: Language) -> String {
+    // The text without tags, and the tags with their position in it and the position of the
+    // matching closing or opening tag
+    let mut text = String::new();
+    let mut tags: Vec<(usize, usize, &str)> = Vec::new();
+    let mut open_tags: Vec<(String, usize)> = Vec::new();
+    for (range, token) in html_tokens::tokenize(html) {
+        match token {
+            Token::Text(escaped) => text.push_str(&html_text::unescape(escaped)),
+            Token::Open { name, .. } => {
+                open_tags.push((name, tags.len()));
+                tags.push((text.len(), text.len(), &html[range]));
+            }
+            Token::Close { name } => {
+                let partner = open_tags
+                    .iter()
+                    .rposition(|(open_name, _)| *open_name == name)
+                    .map(|position| open_tags.drain(position..).next().unwrap().1);
+                let mut tag = (text.len(), text.len(), &html[range]);
+                if let Some(partner) = partner {
+                    tag.1 = tags[partner].0;
+                    tags[partner].1 = text.len();
+                }
+                tags.push(tag);
+            }
+            Token::Comment => tags.push((text.len(), text.len(), &html[range])),
+        }
+    }
+
+    let tokens = match find_tokens(&text, language) {
+        Some(tokens) => tokens,
+        None => return String::from(html),
+    };
+
+    // Events at the same position are ordered: tags closing within the ending token, its
+    // </span>, other tags, the <span> of the starting token, tags opening within it
+    let mut events = Vec::new();
+    for (token, kind) in &tokens {
+        events.push((token.start, 3, Event::SpanOpen(kind)));
+        events.push((token.end, 1, Event::SpanClose));
+    }
+    for &(position, partner, tag) in &tags {
+        let within = tokens.iter().find(|(token, _)| {
+            position != partner
+                && (token.start..=token.end).contains(&position)
+                && (token.start..=token.end).contains(&partner)
+        });
+        let rank = match within {
+            Some((token, _)) if position == token.start => 4,
+            Some((token, _)) if position == token.end => 0,
+            _ => 2,
+        };
+        events.push((position, rank, Event::Tag(tag, within.is_some())));
+    }
+    events.sort_by_key(|(position, rank, _)| (*position, *rank));
+
+    let mut result = String::new();
+    let mut copied_until = 0;
+    let mut open_kind = None;
+    for (position, _, event) in events {
+        result.push_str(&html_text::escape(&text[copied_until..position]));
+        copied_until = position;
+        match event {
+            Event::SpanOpen(kind) => {
+                result.push_str(&format!("<span class=\"hl-{}\">", kind));
+                open_kind = Some(kind);
+            }
+            Event::SpanClose => {
+                result.push_str("</span>");
+                open_kind = None;
+            }
+            Event::Tag(tag, true) => result.push_str(tag),
+            Event::Tag(tag, false) => match open_kind {
+                Some(kind) => {
+                    result.push_str(&format!("</span>{}<span class=\"hl-{}\">", tag, kind))
+                }
+                None => result.push_str(tag),
+            },
+        }
+    }
+    result.push_str(&html_text::escape(&text[copied_until..]));
+
+    result
+}
+
+// Merges the remaining runs of PL paragraphs, one per line, into <pre><code> blocks and
+// highlights them and the ASN.1, OpenAPI and YANG blocks
+pub fn highlight_code_blocks(content: &str) -> String {
+    println!("\tHighlighting code blocks...");
+
+    let mut result = String::new();
+    let mut copied_until = 0;
+    for listing in code_annexes::find_program_listings(content) {
+        let language = detect_language(&listing.lines);
+        result.push_str(&content[copied_until..listing.start]);
+        result.push_str(&format!(
+            "<pre class=\"code\"><code class=\"language-{}\">{}</code></pre>",
+            language.name(),
+            highlight(listing.lines.join("\n").trim_matches('\n'), language)
+        ));
+        copied_until = listing.end;
+    }
+    result.push_str(&content[copied_until..]);
+
+    let pre_re =
+        Regex::new(r#"(?s)<pre class="(?P<class>asn1|openapi|yang)">(?P<code>.*?)</pre>"#).unwrap();
+    String::from(pre_re.replace_all(&result, |cap: &Captures| {
+        let language = match &cap["class"] {
+            "asn1" => Language::Asn1,
+            "openapi" => Language::Yaml,
+            _ => Language::Yang,
+        };
+        format!(
+            "<pre class=\"{}\">{}</pre>",
+            &cap["class"],
+            highlight_html(&cap["code"], language)
+        )
+    }))
+}
+
+#[cfg(test)]
+fn lines(code: &str) -> Vec<String> {
+    code.lines().map(String::from).collect()
+}
+
+#[test]
+fn test_detect_language() {
+    let language = |code: &str| detect_language(&lines(code));
+
+    assert_eq!(
+        language("Foo ::= SEQUENCE {\n\tbar\tINTEGER\n}"),
+        Language::Asn1
+    );
+    assert_eq!(language("{\n  \"foo\": 1\n}"), Language::Json);
+    assert_eq!(
+        language("container foo {\n  leaf bar { type string; }\n}"),
+        Language::Yang
+    );
+    assert_eq!(language("foo:\n  bar: 1\n  - baz"), Language::Yaml);
+    assert_eq!(
+        language("Foo = \"foo\" SP Bar\n   ; comment\nBar = 1*DIGIT"),
+        Language::Abnf
+    );
+    assert_eq!(
+        language("UE                AMF\n |--Registration-->|"),
+        Language::Text
+    );
+}
+
+#[test]
+fn test_highlight() {
+    assert_eq!(
+        highlight("Foo ::= INTEGER (0..15) -- a & b", Language::Asn1),
+        r#"Foo ::= <span class="hl-keyword">INTEGER</span> (<span class="hl-number">0</span>..<span class="hl-number">15</span>) <span class="hl-comment">-- a &amp; b</span>"#
+    );
+    assert_eq!(
+        highlight("{\"foo\": \"bar\", \"baz\": [1, true]}", Language::Json),
+        r#"{<span class="hl-key">&quot;foo&quot;</span>: <span class="hl-string">&quot;bar&quot;</span>, <span class="hl-key">&quot;baz&quot;</span>: [<span class="hl-number">1</span>, <span class="hl-keyword">true</span>]}"#
+    );
+    assert_eq!(
+        highlight("foo: 'a#b' # c\n  - bar: 1", Language::Yaml),
+        r#"<span class="hl-key">foo</span>: <span class="hl-string">'a#b'</span><span class="hl-comment"> # c</span>
+  - <span class="hl-key">bar</span>: <span class="hl-number">1</span>"#
+    );
+    assert_eq!(
+        highlight("Foo = %x41 1*DIGIT ; c", Language::Abnf),
+        r#"<span class="hl-key">Foo</span> = <span class="hl-number">%x41</span> <span class="hl-number">1</span>*<span class="hl-keyword">DIGIT</span> <span class="hl-comment">; c</span>"#
+    );
+    assert_eq!(highlight("a <b>", Language::Text), "a &lt;b&gt;");
+}
+
+#[test]
+fn test_highlight_code_blocks() {
+    let source = r##"<p class="western">Foo</p>
+<p class="PL-western">{</p>
+<p class="PL-western">  "foo": 1</p>
+<p class="PL-western">}</p>
+<p class="western">Bar</p>
+<pre class="asn1"><a id="asn1-Foo">Foo</a> ::= SEQUENCE { bar <a href="#asn1-Bar">Bar</a> } -- baz</pre>"##;
+    let expected = r##"<p class="western">Foo</p>
+<pre class="code"><code class="language-json">{
+  <span class="hl-key">&quot;foo&quot;</span>: <span class="hl-number">1</span>
+}</code></pre>
+<p class="western">Bar</p>
+<pre class="asn1"><a id="asn1-Foo">Foo</a> ::= <span class="hl-keyword">SEQUENCE</span> { bar <a href="#asn1-Bar">Bar</a> } <span class="hl-comment">-- baz</span></pre>"##;
+
+    assert_eq!(highlight_code_blocks(source), expected);
+}
+
+#[test]
+fn test_highlight_code_blocks_openapi() {
+    let source = r##"<pre class="openapi">components:
+  schemas:
+    <a id="openapi-Foo">Foo</a>:
+      type: object # a &amp; b
+      properties:
+        bar:
+          $ref: '<a href="#openapi-Bar">#/components/schemas/Bar</a>'</pre>"##;
+    let expected = r##"<pre class="openapi"><span class="hl-key">components</span>:
+  <span class="hl-key">schemas</span>:
+    <span class="hl-key"><a id="openapi-Foo">Foo</a></span>:
+      <span class="hl-key">type</span>: object<span class="hl-comment"> # a &amp; b</span>
+      <span class="hl-key">properties</span>:
+        <span class="hl-key">bar</span>:
+          <span class="hl-key">$ref</span>: <span class="hl-string">'<a href="#openapi-Bar">#/components/schemas/Bar</a>'</span></pre>"##;
+
+    assert_eq!(highlight_code_blocks(source), expected);
+}
+
+#[test]
+fn test_highlight_html_splits_tokens_around_crossing_tags() {
+    assert_eq!(
+        highlight_html(r#"-- a <b>b -- c</b> d"#, Language::Asn1),
+        r#"<span class="hl-comment">-- a </span><b><span class="hl-comment">b --</span> c</b> d"#
+    );
+}
@@ -0,0 +1,86 @@
+use regex::{Captures, Regex};
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn unescape(html: &str) -> String {
+    let entity_re =
+        Regex::new(r#"&(#(?P<dec>\d+)|#[xX](?P<hex>[\da-fA-F]+)|(?P<name>[a-zA-Z]+));"#).unwrap();
+
+    let result = entity_re.replace_all(html, |cap: &Captures| {
+        let code = if let Some(dec) = cap.name("dec") {
+            dec.as_str().parse::<u32>().ok()
+        } else if let Some(hex) = cap.name("hex") {
+            u32::from_str_radix(hex.as_str(), 16).ok()
+        } else {
+            match &cap["name"] {
+                "amp" => Some('&' as u32),
+                "lt" => Some('<' as u32),
+                "gt" => Some('>' as u32),
+                "quot" => Some('"' as u32),
+                "apos" => Some('\'' as u32),
+                "nbsp" => Some(' ' as u32),
+                _ => None,
+            }
+        };
+
+        match code.and_then(std::char::from_u32) {
+            Some(c) => c.to_string(),
+            None => String::from(&cap[0]),
+        }
+    });
+
+    String::from(result)
+}
+
+// Text of an HTML fragment with tags removed, entities decoded and whitespace collapsed
+pub fn to_plain_text(html: &str) -> String {
+    let block_tag_re =
+        Regex::new(r#"(?is:</?(p|br|div|li|td|th|tr|table|h\d)(\s[^<>]*)?/?>)"#).unwrap();
+    let tag_re = Regex::new(r#"(?s:<[^<>]+?>)"#).unwrap();
+    let text = block_tag_re.replace_all(html, " ");
+    let text = unescape(&tag_re.replace_all(&text, ""));
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Text of a PL paragraph with its tabs and spaces. LibreOffice wraps long lines of the HTML
+// source, these line breaks are spaces, <br/> are line breaks.
+pub fn program_listing_text(html: &str) -> String {
+    let br_re = Regex::new(r#"(?i)<br\s*/?>"#).unwrap();
+    let tag_re = Regex::new(r#"(?s)<[^<>]+?>"#).unwrap();
+
+    let html = html.trim_matches(|c| c == '\n' || c == '\r');
+    let html = html.replace("\r\n", " ").replace('\n', " ");
+    let html = br_re.replace_all(&html, "\n");
+    let text = unescape(&tag_re.replace_all(&html, ""));
+    String::from(text.trim_end())
+}
+
+#[test]
+fn test_escape() {
+    assert_eq!(
+        escape(r#"<a href="x">&</a>"#),
+        "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+    );
+}
+
+#[test]
+fn test_unescape() {
+    assert_eq!(
+        unescape("&lt;b&gt; &amp;amp; &#8217; &#x41; &foo;"),
+        "<b> &amp; \u{2019} A &foo;"
+    );
+}
+
+#[test]
+fn test_to_plain_text() {
+    let source = "<p class=\"western\">\n\tSystem <b>architecture</b>&nbsp;for\n the 5G<sup>th</sup>&nbsp;System;</p><p>Stage 2</p>";
+    assert_eq!(
+        to_plain_text(source),
+        "System architecture for the 5Gth System; Stage 2"
+    );
+}
@@ -0,0 +1,149 @@
+use regex::Regex;
+use std::ops::Range;
+use std::sync::OnceLock;
+
+// A piece of HTML source. Text and attribute values are still escaped.
+#[derive(Debug, PartialEq)]
+pub enum Token<'a> {
+    Text(&'a str),
+    // Lowercase tag name and the source between the name and the closing `>`
+    Open { name: String, attrs: &'a str },
+    Close { name: String },
+    Comment,
+}
+
+// Tokens of the HTML source with their position. A `<` that does not start a tag is text.
+pub fn tokenize(html: &str) -> impl Iterator<Item = (Range<usize>, Token<'_>)> {
+    static TOKEN_RE: OnceLock<Regex> = OnceLock::new();
+    let token_re = TOKEN_RE.get_or_init(|| {
+        Regex::new(
+            r#"(?s)(?P<comment><!--.*?-->)|<(?P<close>/?)(?P<name>[a-zA-Z][a-zA-Z0-9]*)(?P<attrs>(?:[^>"']|"[^"]*"|'[^']*')*)>|(?P<text>[^<]+|<)"#,
+        )
+        .unwrap()
+    });
+
+    token_re.captures_iter(html).map(|cap| {
+        let whole = cap.get(0).unwrap();
+        let range = whole.start()..whole.end();
+        let token = if cap.name("comment").is_some() {
+            Token::Comment
+        } else if let Some(text) = cap.name("text") {
+            Token::Text(text.as_str())
+        } else {
+            let name = cap.name("name").unwrap().as_str().to_lowercase();
+            if cap.name("close").unwrap().as_str().is_empty() {
+                Token::Open {
+                    name,
+                    attrs: cap.name("attrs").unwrap().as_str(),
+                }
+            } else {
+                Token::Close { name }
+            }
+        };
+        (range, token)
+    })
+}
+
+// Attributes of a tag in source order, with lowercase names. Attributes without a value, like
+// `nowrap`, have none.
+pub fn attributes(attrs: &str) -> Vec<(String, Option<&str>)> {
+    static ATTRIBUTE_RE: OnceLock<Regex> = OnceLock::new();
+    let attribute_re = ATTRIBUTE_RE.get_or_init(|| {
+        Regex::new(
+            r#"(?P<name>[a-zA-Z_:][-a-zA-Z0-9_:.]*)(\s*=\s*("(?P<double>[^"]*)"|'(?P<single>[^']*)'|(?P<bare>[^\s"'>]+)))?"#,
+        )
+        .unwrap()
+    });
+
+    attribute_re
+        .captures_iter(attrs)
+        .map(|cap| {
+            let value = cap
+                .name("double")
+                .or_else(|| cap.name("single"))
+                .or_else(|| cap.name("bare"))
+                .map(|value| value.as_str());
+            (cap.name("name").unwrap().as_str().to_lowercase(), value)
+        })
+        .collect()
+}
+
+// Value of the first attribute with the name, still escaped
+pub fn attribute<'a>(attrs: &'a str, name: &str) -> Option<&'a str> {
+    attributes(attrs)
+        .into_iter()
+        .find(|(attribute_name, _)| attribute_name.eq_ignore_ascii_case(name))
+        .and_then(|(_, value)| value)
+}
+
+// A heading with an id, as clause headings are, with its level, id and content
+pub const HEADING_WITH_ID_PATTERN: &str =
+    r#"(?s)<h(?P<level>\d)\s[^>]*\bid="(?P<id>[^"]+)"[^>]*>(?P<heading>.*?)</h\d>"#;
+
+// Level of a heading tag: "h2" is 2. Annex headings go down to <h9>.
+pub fn heading_level(name: &str) -> Option<usize> {
+    match name.as_bytes() {
+        [b'h' | b'H', level @ b'1'..=b'9'] => Some(usize::from(level - b'0')),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_tokenize() {
+    let tokens = tokenize(r#"a<!-- <b> --><P class="x>y">b < c</p>"#)
+        .map(|(range, token)| (range.start, token))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        tokens,
+        vec![
+            (0, Token::Text("a")),
+            (1, Token::Comment),
+            (
+                13,
+                Token::Open {
+                    name: String::from("p"),
+                    attrs: r#" class="x>y""#
+                }
+            ),
+            (28, Token::Text("b ")),
+            (30, Token::Text("<")),
+            (31, Token::Text(" c")),
+            (
+                33,
+                Token::Close {
+                    name: String::from("p")
+                }
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_attributes() {
+    let attrs = r##" HREF="#4.1" nowrap class='a b' width=100 href="x""##;
+
+    assert_eq!(
+        attributes(attrs),
+        vec![
+            (String::from("href"), Some("#4.1")),
+            (String::from("nowrap"), None),
+            (String::from("class"), Some("a b")),
+            (String::from("width"), Some("100")),
+            (String::from("href"), Some("x")),
+        ]
+    );
+    assert_eq!(attribute(attrs, "href"), Some("#4.1"));
+    assert_eq!(attribute(attrs, "nowrap"), None);
+    assert_eq!(attribute(attrs, "id"), None);
+}
+
+#[test]
+fn test_heading_level() {
+    assert_eq!(heading_level("h1"), Some(1));
+    assert_eq!(heading_level("H8"), Some(8));
+    assert_eq!(heading_level("h0"), None);
+    assert_eq!(heading_level("h10"), None);
+    assert_eq!(heading_level("head"), None);
+    assert_eq!(heading_level("hr"), None);
+}
@@ -0,0 +1,444 @@
+use failure::ResultExt;
+use regex::{Captures, Match, Regex};
+use serde::Deserialize;
+
+// 4.2.3.1a // annex subclauses start with the annex letter: D.3.2
+pub const CLAUSE_NO: &str = r#"(\d[\.\da-z]*[\da-z])|([A-Z](\.\d[\da-z]*)+)"#;
+const ANNEX_NO: &str = r#"[A-Z]{1,2}\b"#;
+// Clause numbers after the first one of a list have at least two levels, so "clause 5.3.2, 16
+// bits" is not a list
+const LISTED_CLAUSE_NO: &str = r#"(\d+(\.\d+)+[a-z]?)|([A-Z](\.\d[\da-z]*)+)"#;
+
+// Capture groups a rule can link with:
+// ts_no - the document, "23.501"
+// ts_ref - the part of the match linked to the document when clause_list is used, "TS 23.501 [2]"
+// clause_no - a clause or an annex, "4.2.3" or "B"
+// clause_list - a list of clauses, each of them linked separately, "4.2.2 to 4.2.5"
+// figure_no - a figure, "4.2.3-1"
+// table_no - a table, "4.2.3-1"
+pub const GROUP_NAMES: [&str; 6] = [
+    "ts_no",
+    "ts_ref",
+    "clause_no",
+    "clause_list",
+    "figure_no",
+    "table_no",
+];
+
+#[derive(Deserialize)]
+pub struct LinkRule {
+    pub name: String,
+    pub pattern: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    replace_builtin: bool,
+    #[serde(default, rename = "rule")]
+    rules: Vec<LinkRule>,
+}
+
+enum RuleKind {
+    Clause,
+    Figure,
+}
+
+// Rules joined into one regex. Groups of every rule are suffixed with the rule number, so
+// `clause_no` of the third rule is `clause_no_2`.
+pub struct CompiledRules {
+    regex: Option<Regex>,
+    rule_count: usize,
+}
+
+impl CompiledRules {
+    fn new(rules: &[&LinkRule]) -> Result<CompiledRules, failure::Error> {
+        if rules.is_empty() {
+            return Ok(CompiledRules {
+                regex: None,
+                rule_count: 0,
+            });
+        }
+
+        // Both "(?P<name>" and "(?<name>" name a group
+        let group_re = Regex::new(r#"\(\?P?<(?P<name>[a-z_]+)>"#).unwrap();
+        let numbered_rules = rules
+            .iter()
+            .enumerate()
+            .map(|(rule_no, rule)| {
+                let numbered_group = format!("(?P<${{name}}_{}>", rule_no);
+                String::from(group_re.replace_all(&rule.pattern, numbered_group.as_str()))
+            })
+            .collect::<Vec<_>>();
+
+        let joined = numbered_rules.join(")|(");
+        let complete_regex = format!("(?s:(?P<whole_content>(({}))))", joined);
+
+        let regex = Regex::new(complete_regex.as_str())
+            .with_context(|_| String::from("could not join the rules into one regex"))?;
+        Ok(CompiledRules {
+            regex: Some(regex),
+            rule_count: rules.len(),
+        })
+    }
+
+    pub fn captures_iter<'r, 't: 'r>(
+        &'r self,
+        text: &'t str,
+    ) -> impl Iterator<Item = Captures<'t>> + 'r {
+        self.regex.iter().flat_map(move |re| re.captures_iter(text))
+    }
+
+    pub fn group<'t>(&self, cap: &Captures<'t>, name: &str) -> Option<Match<'t>> {
+        (0..self.rule_count).find_map(|rule_no| cap.name(&format!("{}_{}", name, rule_no)))
+    }
+}
+
+pub struct LinkRules {
+    pub clause_rules: CompiledRules,
+    pub figure_rules: CompiledRules,
+}
+
+impl LinkRules {
+    pub fn builtin() -> LinkRules {
+        LinkRules::compile(&builtin_rules()).unwrap()
+    }
+
+    // Rules of the file go before the built-in ones, so they win when both match at the same place
+    pub fn from_file(path: &std::path::Path) -> Result<LinkRules, failure::Error> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|_| format!("could not read rules file `{}`", path.display()))?;
+
+        let link_rules = LinkRules::from_toml(&content)
+            .with_context(|_| format!("invalid rules file `{}`", path.display()))?;
+        Ok(link_rules)
+    }
+
+    fn from_toml(content: &str) -> Result<LinkRules, failure::Error> {
+        let rules_file: RulesFile =
+            toml::from_str(content).with_context(|_| String::from("could not parse rules"))?;
+
+        let mut rules = rules_file.rules;
+        if !rules_file.replace_builtin {
+            rules.extend(builtin_rules());
+        }
+
+        LinkRules::compile(&rules)
+    }
+
+    fn compile(rules: &[LinkRule]) -> Result<LinkRules, failure::Error> {
+        let mut clause_rules = Vec::new();
+        let mut figure_rules = Vec::new();
+
+        for rule in rules {
+            match validate_rule(rule)? {
+                RuleKind::Clause => clause_rules.push(rule),
+                RuleKind::Figure => figure_rules.push(rule),
+            }
+        }
+
+        Ok(LinkRules {
+            clause_rules: CompiledRules::new(&clause_rules)?,
+            figure_rules: CompiledRules::new(&figure_rules)?,
+        })
+    }
+}
+
+fn validate_rule(rule: &LinkRule) -> Result<RuleKind, failure::Error> {
+    let error = |message: String| failure::err_msg(format!("rule `{}`: {}", rule.name, message));
+
+    let re = Regex::new(&rule.pattern).map_err(|e| error(format!("invalid pattern: {}", e)))?;
+
+    let group_names = re.capture_names().flatten().collect::<Vec<&str>>();
+    if let Some(unknown) = group_names.iter().find(|n| !GROUP_NAMES.contains(n)) {
+        return Err(error(format!(
+            "unknown capture group `{}`, expected one of: {}",
+            unknown,
+            GROUP_NAMES.join(", ")
+        )));
+    }
+
+    let has = |name: &str| group_names.contains(&name);
+    let links_clause = has("clause_no") || has("clause_list");
+    let links_figure = has("figure_no") || has("table_no");
+
+    if links_clause && links_figure {
+        return Err(error(String::from(
+            "links both a clause and a figure or table",
+        )));
+    }
+    if !links_clause && !links_figure && !has("ts_no") {
+        return Err(error(String::from(
+            "has none of the capture groups ts_no, clause_no, clause_list, figure_no, table_no",
+        )));
+    }
+    if has("ts_ref") && !has("ts_no") {
+        return Err(error(String::from("has ts_ref group without ts_no group")));
+    }
+    if re.is_match("") {
+        return Err(error(String::from("matches empty text")));
+    }
+
+    if links_figure {
+        Ok(RuleKind::Figure)
+    } else {
+        Ok(RuleKind::Clause)
+    }
+}
+
+fn builtin_rules() -> Vec<LinkRule> {
+    // 4.2.2 to 4.2.5 // 5.3.2, 5.3.3 and 5.3.4 // the separators can be mixed
+    let clause_list = format!(
+        r#"({0})((\s*,\s*((and)|(or))?\s*|\s+((and)|(or)|(to))\s+)({1}))+"#,
+        CLAUSE_NO, LISTED_CLAUSE_NO
+    );
+
+    let rules = vec![
+        // TS 23.501 [2], clauses 5.4.4.1 and 5.4.4.2 // the comma is optional
+        (
+            "ts-clause-list",
+            format!(
+                r#"(?P<ts_ref>((TS\s+)?(?P<ts_no>(\d{{2}}\.\d{{3}}))\s+\[\d+\])),?\s+[cC]lauses?\s+(?P<clause_list>({}))"#,
+                clause_list
+            ),
+        ),
+        // clauses 5.3.2 and 5.3.3 (Some text) of TS 23.401 [13] // "(Some text)" and "[13]" are optional, "of" can be "in"
+        (
+            "clause-list-of-ts",
+            format!(
+                r#"(((in)|(see))\s+)?[cC]lauses?\s+(?P<clause_list>({}))\s+(\([^<^>.]+\)\s+)?((of)|(in))\s+(?P<ts_ref>(TS\s+(?P<ts_no>(\d{{2}}\.\d{{3}}))(\s+\[\d+\])?))"#,
+                clause_list
+            ),
+        ),
+        // in clauses 4.2.2 to 4.2.5 // "in" can be "see" and it is optional
+        (
+            "clause-list",
+            format!(
+                r#"(((in)|(see))\s+)?[cC]lauses?\s+(?P<clause_list>({}))"#,
+                clause_list
+            ),
+        ),
+        // TS 23.501 [2], clause 5.4.4.1b // the comma is optional, the last letter is optional
+        (
+            "ts-clause",
+            format!(
+                r#"(TS\s+)?(?P<ts_no>(\d{{2}}\.\d{{3}}))\s+\[\d+\],?\s+[cC]lause\s+(?P<clause_no>({}))"#,
+                CLAUSE_NO
+            ),
+        ),
+        // clause 5.3.3.1 (Some text) in TS 23.401 [13] // "(Some text)" and "[13]" are optional, "in" can be "of"
+        (
+            "clause-of-ts",
+            format!(
+                r#"(((in)|(see))\s+)?[cC]lause\s+(?P<clause_no>({}))\s+(\([^<^>.]+\)\s+)?((of)|(in))\s+TS\s+(?P<ts_no>(\d{{2}}\.\d{{3}}))(\s+\[\d+\])?"#,
+                CLAUSE_NO
+            ),
+        ),
+        // in clause 4.4 // "in" can be "see" and it is optional
+        (
+            "clause",
+            format!(
+                r#"(((in)|(see))\s+)?[cC]lause\s+(?P<clause_no>({}))"#,
+                CLAUSE_NO
+            ),
+        ),
+        // TS 23.501 [2], Annex B // the comma is optional, "Annex" can be lowercase
+        (
+            "ts-annex",
+            format!(
+                r#"(TS\s+)?(?P<ts_no>(\d{{2}}\.\d{{3}}))\s+\[\d+\],?\s+[aA]nnex\s+(?P<clause_no>({}))"#,
+                ANNEX_NO
+            ),
+        ),
+        // Annex B (Some text) of TS 23.401 [13] // "(Some text)" and "[13]" are optional, "of" can be "in"
+        (
+            "annex-of-ts",
+            format!(
+                r#"(((in)|(see))\s+)?[aA]nnex\s+(?P<clause_no>({}))\s+(\([^<^>.]+\)\s+)?((of)|(in))\s+TS\s+(?P<ts_no>(\d{{2}}\.\d{{3}}))(\s+\[\d+\])?"#,
+                ANNEX_NO
+            ),
+        ),
+        // in Annex B // "in" can be "see" and it is optional
+        (
+            "annex",
+            format!(
+                r#"(((in)|(see))\s+)?[aA]nnex\s+(?P<clause_no>({}))"#,
+                ANNEX_NO
+            ),
+        ),
+        // in 4.3.3.2 // "in" can be "see" and it is mandatory
+        (
+            "in-clause-no",
+            format!(r#"((in)|(see))\s+(?P<clause_no>({}))"#, CLAUSE_NO),
+        ),
+        // TS 23.501 [2]
+        (
+            "ts",
+            String::from(r#"(TS\s+)?(?P<ts_no>(\d{2}\.\d{3}))\s+\[\d+\]"#),
+        ),
+        // Figure 4.2.3-1
+        (
+            "figure",
+            String::from(r#"[Ff]igure\s+(?P<figure_no>(\d[\.\d\-a-z]*-\d+))"#),
+        ),
+        // Table 4.2.3-1
+        (
+            "table",
+            String::from(r#"[Tt]able\s+(?P<table_no>(\d[\.\d\-a-z]*-\d+))"#),
+        ),
+    ];
+
+    rules
+        .into_iter()
+        .map(|(name, pattern)| LinkRule {
+            name: String::from(name),
+            pattern,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn error_messages(error: failure::Error) -> String {
+    error
+        .iter_chain()
+        .map(|cause| cause.to_string())
+        .collect::<Vec<_>>()
+        .join(": ")
+}
+
+#[test]
+fn test_link_rules_builtin_rules_are_valid() {
+    for rule in builtin_rules() {
+        assert!(validate_rule(&rule).is_ok(), "rule `{}`", rule.name);
+    }
+}
+
+#[test]
+fn test_link_rules_compiled_rules_have_numbered_groups() {
+    let rule = LinkRule {
+        name: String::from("subclause"),
+        pattern: String::from(r#"[sS]ubclause\s+(?P<clause_no>\d+)"#),
+    };
+    let compiled = CompiledRules::new(&[&rule, &rule]).unwrap();
+
+    let cap = compiled.captures_iter("foo subclause 4").next().unwrap();
+    assert_eq!(compiled.group(&cap, "clause_no").unwrap().as_str(), "4");
+    assert!(cap.name("clause_no_0").is_some());
+}
+
+#[test]
+fn test_link_rules_compiled_rules_accept_both_group_syntaxes() {
+    let subclause = LinkRule {
+        name: String::from("subclause"),
+        pattern: String::from(r#"[sS]ubclause\s+(?<clause_no>\d+)"#),
+    };
+    let section = LinkRule {
+        name: String::from("section"),
+        pattern: String::from(r#"§\s*(?<clause_no>\d+)"#),
+    };
+    let compiled = CompiledRules::new(&[&subclause, &section, &subclause]).unwrap();
+
+    let cap = compiled.captures_iter("foo § 5").next().unwrap();
+    assert_eq!(compiled.group(&cap, "clause_no").unwrap().as_str(), "5");
+    assert!(cap.name("clause_no_1").is_some());
+}
+
+#[test]
+fn test_link_rules_from_toml_replaces_builtin_rules() {
+    let rules = LinkRules::from_toml(
+        r#"
+replace_builtin = true
+
+[[rule]]
+name = "section"
+pattern = '§\s*(?P<clause_no>\d+(\.\d+)*)'
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(rules.clause_rules.rule_count, 1);
+    assert_eq!(rules.figure_rules.rule_count, 0);
+}
+
+#[test]
+fn test_link_rules_from_toml_adds_to_builtin_rules() {
+    let rules = LinkRules::from_toml(
+        r#"
+[[rule]]
+name = "table"
+pattern = 'Tab\.\s*(?P<table_no>\d[\.\d]*-\d+)'
+"#,
+    )
+    .unwrap();
+
+    let builtin = LinkRules::builtin();
+    assert_eq!(
+        rules.clause_rules.rule_count,
+        builtin.clause_rules.rule_count
+    );
+    assert_eq!(
+        rules.figure_rules.rule_count,
+        builtin.figure_rules.rule_count + 1
+    );
+}
+
+#[test]
+fn test_link_rules_from_toml_rejects_unknown_group() {
+    let error = LinkRules::from_toml(
+        r#"
+[[rule]]
+name = "subclause"
+pattern = 'sub-clause\s+(?P<clause>\d+)'
+"#,
+    )
+    .err()
+    .unwrap();
+
+    let message = error_messages(error);
+    assert!(message.contains("rule `subclause`: unknown capture group `clause`"));
+}
+
+#[test]
+fn test_link_rules_from_toml_rejects_invalid_pattern() {
+    let error = LinkRules::from_toml(
+        r#"
+[[rule]]
+name = "broken"
+pattern = '(?P<clause_no>\d+'
+"#,
+    )
+    .err()
+    .unwrap();
+
+    assert!(error_messages(error).contains("rule `broken`: invalid pattern"));
+}
+
+#[test]
+fn test_link_rules_from_toml_rejects_rule_without_link_groups() {
+    let error = LinkRules::from_toml(
+        r#"
+[[rule]]
+name = "nothing"
+pattern = 'according to \d+'
+"#,
+    )
+    .err()
+    .unwrap();
+
+    assert!(error_messages(error).contains("rule `nothing`: has none of the capture groups"));
+}
+
+#[test]
+fn test_link_rules_from_toml_rejects_unknown_keys() {
+    let error = LinkRules::from_toml("replace_builtins = true\n")
+        .err()
+        .unwrap();
+    assert!(error_messages(error).contains("could not parse rules"));
+}
+
+#[test]
+fn test_link_rules_example_file_is_valid() {
+    let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("link_rules.example.toml");
+    assert!(LinkRules::from_file(&path).is_ok());
+}
@@ -0,0 +1,201 @@
+use crate::html_tokens;
+use crate::source_parser;
+
+use regex::Regex;
+
+// Elements whose text must stay untouched by the link passes, with the headings
+const SKIPPED_TAGS: [&str; 6] = ["a", "pre", "code", "script", "style", "title"];
+
+const ASN1_START: &str = "-- ASN1START";
+const ASN1_STOP: &str = "-- ASN1STOP";
+
+// Calls `replace` for every piece of text that a link can be inserted into, together with the id
+// of the clause heading the text belongs to. Tags (with their attributes), comments, text inside
+// the skipped elements, inside paragraphs of the PL (program listing) style and between ASN.1
+// markers are copied as they are.
+pub fn replace_linkable_text<F>(content: &str, mut replace: F) -> String
+where
+    F: FnMut(&str, Option<&str>) -> String,
+{
+    let pl_paragraph_re = Regex::new(r#"(?i)^<p\s[^>]*class="pl[-"]"#).unwrap();
+    let heading_id_re = Regex::new(r#"(?i)^<h\d\s[^>]*id="(?P<id>[^"]+)""#).unwrap();
+
+    let mut parser = source_parser::SourceParser::new(content);
+    let mut result = String::new();
+    let mut open_skipped_tags: Vec<String> = Vec::new();
+    let mut in_asn1 = false;
+    let mut current_clause: Option<String> = None;
+
+    while !parser.is_at_end() {
+        let text = parser.goto_begin_of_and_get_omitted_content("<").unwrap();
+        if open_skipped_tags.is_empty() {
+            let mut replace_in_clause = |text: &str| replace(text, current_clause.as_deref());
+            push_text(&mut result, text, &mut in_asn1, &mut replace_in_clause);
+        } else {
+            // Markers in skipped elements still open and close ASN.1 blocks
+            update_asn1_state(text, &mut in_asn1);
+            result.push_str(text);
+        }
+
+        if parser.is_at_end() {
+            break;
+        }
+
+        let tag_end = if parser.get_content_til_end_of_source().starts_with("<!--") {
+            "-->"
+        } else {
+            ">"
+        };
+        let tag = parser.goto_end_of_and_get_omitted_content(tag_end).unwrap();
+        result.push_str(tag);
+
+        let (name, is_closing) = tag_name(tag);
+        if let Some(cap) = heading_id_re.captures(tag) {
+            current_clause = Some(String::from(&cap["id"]));
+        }
+
+        if is_closing {
+            if let Some(pos) = open_skipped_tags.iter().rposition(|t| *t == name) {
+                open_skipped_tags.truncate(pos);
+            }
+        } else if !tag.ends_with("/>")
+            && (SKIPPED_TAGS.contains(&name.as_str())
+                || html_tokens::heading_level(&name).is_some()
+                || pl_paragraph_re.is_match(tag))
+        {
+            open_skipped_tags.push(name);
+        }
+    }
+
+    result
+}
+
+fn push_text<F>(result: &mut String, text: &str, in_asn1: &mut bool, replace: &mut F)
+where
+    F: FnMut(&str) -> String,
+{
+    let mut parser = source_parser::SourceParser::new(text);
+
+    while !parser.is_at_end() {
+        if *in_asn1 {
+            *in_asn1 = !parser.is_before_end(ASN1_STOP);
+            result.push_str(
+                parser
+                    .goto_end_of_and_get_omitted_content(ASN1_STOP)
+                    .unwrap(),
+            );
+        } else {
+            *in_asn1 = parser.is_before_end(ASN1_START);
+            let linkable = parser
+                .goto_begin_of_and_get_omitted_content(ASN1_START)
+                .unwrap();
+            result.push_str(&replace(linkable));
+        }
+    }
+}
+
+fn update_asn1_state(text: &str, in_asn1: &mut bool) {
+    match (text.rfind(ASN1_START), text.rfind(ASN1_STOP)) {
+        (Some(start), Some(stop)) => *in_asn1 = start > stop,
+        (Some(_), None) => *in_asn1 = true,
+        (None, Some(_)) => *in_asn1 = false,
+        (None, None) => {}
+    }
+}
+
+// Lowercase name of the tag and whether it is a closing one. Comments and doctypes have no name.
+fn tag_name(tag: &str) -> (String, bool) {
+    let is_closing = tag.starts_with("</");
+    let name = tag
+        .trim_start_matches('<')
+        .trim_start_matches('/')
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+
+    (name, is_closing)
+}
+
+#[test]
+fn test_replace_linkable_text_replaces_text_only() {
+    let source = r#"<p style="margin: 0.5in 1.5in">foo</p>bar"#;
+    let expected = r#"<p style="margin: 0.5in 1.5in">FOO</p>BAR"#;
+    assert_eq!(
+        replace_linkable_text(source, |text, _| text.to_uppercase()),
+        expected
+    )
+}
+
+#[test]
+fn test_replace_linkable_text_skips_anchors_and_headings() {
+    let source =
+        r##"<a href="#1"><p>foo <a href="#2">bar</a> baz</p></a> <h1 id="1">qux</h1> top"##;
+    let expected =
+        r##"<a href="#1"><p>foo <a href="#2">bar</a> baz</p></a> <h1 id="1">qux</h1> TOP"##;
+    assert_eq!(
+        replace_linkable_text(source, |text, _| text.to_uppercase()),
+        expected
+    )
+}
+
+#[test]
+fn test_replace_linkable_text_skips_annex_headings() {
+    let source = r#"<h8 id="A.1.1">foo</h8>bar<h9 id="A.1.1.1">baz</h9>"#;
+    let expected = r#"<h8 id="A.1.1">foo</h8>BAR<h9 id="A.1.1.1">baz</h9>"#;
+    assert_eq!(
+        replace_linkable_text(source, |text, _| text.to_uppercase()),
+        expected
+    )
+}
+
+#[test]
+fn test_replace_linkable_text_skips_code() {
+    let source = r#"<p class="PL-western">foo</p><pre>bar</pre><p class="western">baz</p>"#;
+    let expected = r#"<p class="PL-western">foo</p><pre>bar</pre><p class="western">BAZ</p>"#;
+    assert_eq!(
+        replace_linkable_text(source, |text, _| text.to_uppercase()),
+        expected
+    )
+}
+
+#[test]
+fn test_replace_linkable_text_skips_asn1() {
+    let source = "<p>foo</p><p>-- ASN1START</p><p>bar</p><p>-- ASN1STOP</p><p>baz</p>";
+    let expected = "<p>FOO</p><p>-- ASN1START</p><p>bar</p><p>-- ASN1STOP</p><p>BAZ</p>";
+    assert_eq!(
+        replace_linkable_text(source, |text, _| text.to_uppercase()),
+        expected
+    )
+}
+
+#[test]
+fn test_replace_linkable_text_closes_asn1_in_skipped_elements() {
+    let source = "<p>foo</p><p>-- ASN1START</p><pre>bar\n-- ASN1STOP</pre><p>baz</p>";
+    let expected = "<p>FOO</p><p>-- ASN1START</p><pre>bar\n-- ASN1STOP</pre><p>BAZ</p>";
+    assert_eq!(
+        replace_linkable_text(source, |text, _| text.to_uppercase()),
+        expected
+    )
+}
+
+#[test]
+fn test_replace_linkable_text_skips_comments() {
+    let source = "foo<!-- <b> bar -->baz";
+    let expected = "FOO<!-- <b> bar -->BAZ";
+    assert_eq!(
+        replace_linkable_text(source, |text, _| text.to_uppercase()),
+        expected
+    )
+}
+
+#[test]
+fn test_replace_linkable_text_passes_current_clause() {
+    let source =
+        r#"foo<h1 id="4.1" class="western">4.1 Bar</h1>baz<h2>Qux</h2>top<h2 id="4.2">4.2</h2>kek"#;
+    let expected = r#"foo:None<h1 id="4.1" class="western">4.1 Bar</h1>baz:4.1<h2>Qux</h2>top:4.1<h2 id="4.2">4.2</h2>kek:4.2"#;
+    let result = replace_linkable_text(source, |text, clause| {
+        format!("{}:{}", text, clause.unwrap_or("None"))
+    });
+    assert_eq!(result, expected)
+}
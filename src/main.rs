@@ -1,22 +1,136 @@
+mod asn1;
+mod backlinks;
+mod chunks;
+mod clause_index;
+mod code_annexes;
+mod document_model;
+mod epub;
+mod glossary;
+mod glossary_page;
+mod highlight;
+mod html_text;
+mod html_tokens;
+mod link_rules;
+mod linkable_text;
+mod markdown;
+mod previews;
+mod references;
 mod rich_html;
+mod search_index;
+mod site_index;
+mod source_modifier;
+mod source_parser;
+mod spec_graph;
+mod spec_metadata;
+mod template;
+mod toc;
+mod zip_archive;
 
+use clause_index::ClauseIndex;
+use epub::EpubDocument;
 use exitfailure::ExitFailure;
 use failure::ResultExt;
+use glossary::Glossary;
+use link_rules::LinkRules;
+use references::DocumentReferences;
+use spec_metadata::SpecMetadata;
 use structopt::StructOpt;
+use template::Template;
 
 #[derive(StructOpt)]
 struct CliArgs {
     #[structopt(parse(from_os_str))]
-    input_dir: std::path::PathBuf,
+    input_dir: Option<std::path::PathBuf>,
     #[structopt(parse(from_os_str))]
-    output_dir: std::path::PathBuf,
+    output_dir: Option<std::path::PathBuf>,
+    /// TOML file with reference patterns added to (or replacing) the built-in ones
+    #[structopt(long, parse(from_os_str))]
+    rules: Option<std::path::PathBuf>,
+    /// HTML page template with {{title}}, {{metadata}}, {{style}}, {{toc}} and {{body}} placeholders
+    #[structopt(long, parse(from_os_str))]
+    template: Option<std::path::PathBuf>,
+    /// Removes LibreOffice styles, so pages only use the theme of the template
+    #[structopt(long)]
+    strip_styles: bool,
+    /// Output formats, separated by commas: html, markdown, epub, json
+    #[structopt(
+        long = "format",
+        default_value = "html",
+        use_delimiter = true,
+        number_of_values = 1
+    )]
+    formats: Vec<OutputFormat>,
+    /// Splits documents into a page per clause of at most this many levels, e.g. 1 for "5", 2 for "5.6"
+    #[structopt(long)]
+    chunk_depth: Option<usize>,
+    /// Also packages all converted documents in one EPUB book at this path
+    #[structopt(long, parse(from_os_str))]
+    epub_bundle: Option<std::path::PathBuf>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(PartialEq, Clone, Copy)]
+enum OutputFormat {
+    Html,
+    Markdown,
+    Epub,
+    Json,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutputFormat, String> {
+        match s {
+            "html" => Ok(OutputFormat::Html),
+            "markdown" | "md" => Ok(OutputFormat::Markdown),
+            "epub" => Ok(OutputFormat::Epub),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format `{}`", s)),
+        }
+    }
+}
+
+// How handle_file writes the enriched documents
+struct OutputOptions {
+    formats: Vec<OutputFormat>,
+    template: Template,
+    strip_styles: bool,
+}
+
+// Queries of the clause index written by a previous conversion
+#[derive(StructOpt)]
+enum Command {
+    /// Lists clauses containing all words of the query, best matches first
+    Search {
+        query: String,
+        /// Output directory of the conversion
+        #[structopt(long, short, parse(from_os_str), default_value = ".")]
+        dir: std::path::PathBuf,
+        /// Maximum number of listed clauses
+        #[structopt(long, short = "n", default_value = "20")]
+        limit: usize,
+        #[structopt(long)]
+        markdown: bool,
+    },
+    /// Prints the text of a clause, e.g. `show 23.502 4.3.2.2.1`
+    Show {
+        ts_no: String,
+        clause: String,
+        /// Output directory of the conversion
+        #[structopt(long, short, parse(from_os_str), default_value = ".")]
+        dir: std::path::PathBuf,
+        #[structopt(long)]
+        markdown: bool,
+    },
 }
 
 fn gather_paths_with_extension(
     dir: &std::path::PathBuf,
     ext: &str,
 ) -> Result<Vec<std::path::PathBuf>, ExitFailure> {
-    let files = std::fs::read_dir(&dir)?;
+    let files = std::fs::read_dir(dir)?;
 
     let mut pdf_paths = Vec::new();
 
@@ -33,10 +147,10 @@ fn gather_paths_with_extension(
         };
     }
 
-    return Ok(pdf_paths);
+    Ok(pdf_paths)
 }
 
-fn extract_ts_number_from_file_path(path: &std::path::PathBuf) -> Option<String> {
+fn extract_ts_number_from_file_path(path: &std::path::Path) -> Option<String> {
     let filename = path.file_stem().unwrap().to_str().unwrap();
     let ts_number_and_version = filename.split("-").collect::<Vec<&str>>();
     if ts_number_and_version.len() < 2 {
@@ -44,22 +158,32 @@ fn extract_ts_number_from_file_path(path: &std::path::PathBuf) -> Option<String>
     }
 
     let ts_number = ts_number_and_version[0];
-    return Some(format!("{}.{}", &ts_number[..2], &ts_number[2..]));
+    Some(format!("{}.{}", &ts_number[..2], &ts_number[2..]))
 }
 
-fn docx_to_html(
-    path: &std::path::PathBuf,
-    out_path: &std::path::PathBuf,
-) -> Result<String, ExitFailure> {
-    let out_path = out_path.to_str().unwrap();
-    let output_file_name = format!(
+// A document converted to HTML by LibreOffice, waiting to be enriched
+struct ConvertedFile {
+    ts_no: String,
+    output_dir: String,
+    html_path: String,
+    metadata: SpecMetadata,
+    glossary: Glossary,
+}
+
+fn converted_html_path(path: &std::path::Path, out_path: &std::path::Path) -> String {
+    format!(
         "{}/{}.html",
-        out_path,
+        out_path.to_string_lossy(),
         path.file_stem().unwrap().to_string_lossy()
-    );
+    )
+}
+
+fn docx_to_html(path: &std::path::Path, out_path: &std::path::Path) -> Result<String, ExitFailure> {
+    let output_file_name = converted_html_path(path, out_path);
+    let out_path = out_path.to_str().unwrap();
 
     let _output = std::process::Command::new("lowriter")
-        .args(&[
+        .args([
             "--convert-to",
             "html",
             path.to_str().unwrap(),
@@ -81,10 +205,10 @@ fn docx_to_html(
     Ok(html_content)
 }
 
-fn handle_file(
-    path: &std::path::PathBuf,
-    out_path: &std::path::PathBuf,
-) -> Result<(), ExitFailure> {
+fn convert_file(
+    path: &std::path::Path,
+    out_path: &std::path::Path,
+) -> Result<(ConvertedFile, String), ExitFailure> {
     let ts_number = extract_ts_number_from_file_path(path);
     if ts_number.is_none() {
         return Err(failure::err_msg(
@@ -96,23 +220,228 @@ fn handle_file(
     let ts_no = ts_number.unwrap();
 
     let output_dir = format!("{}/{}", &out_path.to_string_lossy(), ts_no);
+    let output_dir_path = std::path::PathBuf::from(&output_dir);
+
+    let html_content = docx_to_html(path, &output_dir_path)?;
+
+    let mut metadata = spec_metadata::extract_metadata(&html_content, &ts_no);
+    let file_name_version = spec_metadata::extract_version_from_file_path(path);
+    if let Some(warning) =
+        spec_metadata::cross_check_version(&mut metadata, file_name_version.as_deref())
+    {
+        println!("\tWarning: {}", warning);
+    }
+
+    let glossary = glossary::extract_glossary(&html_content, &ts_no);
+
+    let converted_file = ConvertedFile {
+        html_path: converted_html_path(path, &output_dir_path),
+        ts_no,
+        output_dir,
+        metadata,
+        glossary,
+    };
+
+    Ok((converted_file, html_content))
+}
+
+// Returns the enriched document
+fn handle_file(
+    file: &ConvertedFile,
+    clause_index: &ClauseIndex,
+    link_rules: &LinkRules,
+    vocabulary: Option<&Glossary>,
+    options: &OutputOptions,
+) -> Result<String, ExitFailure> {
+    let html_content = std::fs::read_to_string(&file.html_path)
+        .with_context(|_| format!("could not read converted html file `{}`", file.html_path))?;
+    let terms = glossary::defined_terms(&file.glossary, vocabulary);
+    let (html_content, references) = rich_html::enrich_html(
+        &html_content,
+        &file.metadata,
+        clause_index,
+        link_rules,
+        &terms,
+    );
+
+    if options.formats.contains(&OutputFormat::Html) {
+        previews::save_previews(&file.output_dir, &file.ts_no, &html_content)?;
+        let page =
+            options
+                .template
+                .render_document(&html_content, &file.metadata, options.strip_styles);
 
-    let html_content = docx_to_html(path, &std::path::PathBuf::from(&output_dir))?;
-    let html_content = rich_html::enrich_html(&html_content);
+        let output_file_path = format!("{}/{}.html", file.output_dir, file.ts_no);
+        std::fs::write(&output_file_path, &page)
+            .with_context(|_| format!("could not write HTML file `{}`", &output_file_path))?;
+    }
 
-    let output_file_path = format!("{}/{}.html", output_dir, ts_no);
-    std::fs::write(&output_file_path, &html_content)
-        .with_context(|_| format!("could not write HTML file `{}`", &output_file_path))?;
+    if options.formats.contains(&OutputFormat::Markdown) {
+        let output_file_path = format!("{}/{}.md", file.output_dir, file.ts_no);
+        std::fs::write(&output_file_path, markdown::html_to_markdown(&html_content))
+            .with_context(|_| format!("could not write Markdown file `{}`", &output_file_path))?;
+    }
+
+    if options.formats.contains(&OutputFormat::Json) {
+        let output_file_path = format!("{}/{}.json", file.output_dir, file.ts_no);
+        let model = document_model::build_document_model(&html_content, &file.metadata);
+        std::fs::write(&output_file_path, serde_json::to_string_pretty(&model)?)
+            .with_context(|_| format!("could not write JSON file `{}`", &output_file_path))?;
+    }
+
+    if options.formats.contains(&OutputFormat::Epub) {
+        let output_file_path = format!("{}/{}.epub", file.output_dir, file.ts_no);
+        let document = epub_document(file, html_content.clone());
+        epub::save_epub(std::path::Path::new(&output_file_path), &[document])?;
+    }
+
+    let document_references = DocumentReferences {
+        ts_no: file.ts_no.clone(),
+        references,
+    };
+    references::save_references(&file.output_dir, &document_references)?;
+    glossary::save_glossary(&file.output_dir, &file.glossary)?;
+    asn1::save_asn1_modules(&file.output_dir, &file.ts_no, &html_content)?;
+    code_annexes::save_code_annexes(&file.output_dir, &file.ts_no, &html_content)?;
+    spec_metadata::save_metadata(&file.output_dir, &file.metadata)?;
+
+    Ok(html_content)
+}
+
+// Images of a converted document are next to its HTML file
+fn epub_document(file: &ConvertedFile, content: String) -> EpubDocument {
+    EpubDocument {
+        metadata: file.metadata.clone(),
+        content,
+        base_dir: std::path::PathBuf::from(&file.output_dir),
+    }
+}
+
+fn run_command(command: &Command) -> Result<(), ExitFailure> {
+    match command {
+        Command::Search {
+            query,
+            dir,
+            limit,
+            markdown,
+        } => {
+            let entries = search_index::load_search_index(dir)?;
+            for entry in search_index::search(&entries, query).iter().take(*limit) {
+                println!("{}", search_index::format_hit(entry, *markdown));
+            }
+        }
+        Command::Show {
+            ts_no,
+            clause,
+            dir,
+            markdown,
+        } => {
+            let entries = search_index::load_search_index(dir)?;
+            match search_index::find_clause(&entries, ts_no, clause) {
+                Some(entry) => print!("{}", search_index::format_clause(entry, *markdown)),
+                None => {
+                    return Err(failure::err_msg(format!(
+                        "clause {} of TS {} not found in the search index",
+                        clause, ts_no
+                    ))
+                    .into())
+                }
+            }
+        }
+    }
 
     Ok(())
 }
 
 fn main() -> Result<(), ExitFailure> {
     let args = CliArgs::from_args();
-    let file_paths = gather_paths_with_extension(&args.input_dir, "doc")?;
+    if let Some(command) = &args.command {
+        return run_command(command);
+    }
+
+    let (input_dir, output_dir) = match (args.input_dir, args.output_dir) {
+        (Some(input_dir), Some(output_dir)) => (input_dir, output_dir),
+        _ => structopt::clap::Error::with_description(
+            "<input-dir> and <output-dir> are required unless a subcommand is given",
+            structopt::clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit(),
+    };
+    // Chunks are split from the HTML pages
+    if args.chunk_depth.is_some() && !args.formats.contains(&OutputFormat::Html) {
+        structopt::clap::Error::with_description(
+            "--chunk-depth requires the html format",
+            structopt::clap::ErrorKind::ArgumentConflict,
+        )
+        .exit();
+    }
+    let link_rules = match &args.rules {
+        Some(path) => LinkRules::from_file(path)?,
+        None => LinkRules::builtin(),
+    };
+    let options = OutputOptions {
+        template: match &args.template {
+            Some(path) => Template::from_file(path)?,
+            None => Template::builtin(),
+        },
+        formats: args.formats.clone(),
+        strip_styles: args.strip_styles,
+    };
+    let file_paths = gather_paths_with_extension(&input_dir, "doc")?;
+
+    // All documents are converted first, so links can be checked against every one of them
+    let mut converted_files = Vec::new();
+    let mut clause_index = ClauseIndex::new();
     for p in file_paths {
         println!("{}", p.to_string_lossy());
-        handle_file(&p, &args.output_dir)?;
+        let (converted_file, html_content) = convert_file(&p, &output_dir)?;
+        clause_index.insert(
+            &converted_file.ts_no,
+            rich_html::collect_clause_ids(&html_content),
+        );
+        converted_files.push(converted_file);
+    }
+
+    // Terms of TR 21.905 come from this run or from a previous one
+    let vocabulary = match converted_files
+        .iter()
+        .find(|f| f.ts_no == glossary::VOCABULARY_TS_NO)
+    {
+        Some(f) => Some(f.glossary.clone()),
+        None => glossary::load_glossary(&output_dir.join(glossary::VOCABULARY_TS_NO))?,
+    };
+
+    let mut epub_documents = Vec::new();
+    for f in &converted_files {
+        println!("{}", f.html_path);
+        let html_content =
+            handle_file(f, &clause_index, &link_rules, vocabulary.as_ref(), &options)?;
+        if args.epub_bundle.is_some() {
+            epub_documents.push(epub_document(f, html_content));
+        }
+    }
+    if let Some(path) = &args.epub_bundle {
+        epub::save_epub(path, &epub_documents)?;
+    }
+
+    // References are saved for every format
+    spec_graph::export_spec_graph(&output_dir)?;
+
+    // The site is built from the HTML documents
+    if !options.formats.contains(&OutputFormat::Html) {
+        return Ok(());
+    }
+
+    backlinks::add_backlinks(&output_dir)?;
+    site_index::generate_site_index(&output_dir)?;
+    glossary_page::generate_glossary_page(&output_dir)?;
+    search_index::generate_search_index(&output_dir)?;
+    if let Some(depth) = args.chunk_depth {
+        let ts_nos = converted_files
+            .iter()
+            .map(|f| f.ts_no.clone())
+            .collect::<Vec<_>>();
+        chunks::split_documents(&output_dir, &ts_nos, depth)?;
     }
 
     Ok(())
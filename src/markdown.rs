@@ -0,0 +1,545 @@
+use crate::html_text;
+use crate::html_tokens::{self, attribute, Token};
+
+use regex::{Captures, Regex};
+use std::sync::OnceLock;
+
+// With the headings
+const BLOCK_TAGS: [&str; 12] = [
+    "p",
+    "div",
+    "li",
+    "ul",
+    "ol",
+    "table",
+    "tr",
+    "td",
+    "th",
+    "pre",
+    "blockquote",
+    "dl",
+];
+const SKIPPED_TAGS: [&str; 5] = ["script", "style", "title", "button", "nav"];
+
+// Converts an enriched document to Markdown. Headings, tables and figures keep their ids as
+// anchors, tables with merged cells stay HTML tables and notes become block quotes. Links to
+// other documents point to their Markdown files.
+pub fn html_to_markdown(content: &str) -> String {
+    println!("\tWriting Markdown...");
+
+    let body_re = Regex::new(r#"(?is)<body[^>]*>(?P<body>.*)</body>"#).unwrap();
+    // LibreOffice TOC entries are paragraphs inside links
+    let toc_entry_re = Regex::new(
+        r#"(?is)<a href="(?P<href>[^"]*)">\s*<p(?P<attrs>[^>]*)>(?P<text>.*?)</p>\s*</a>"#,
+    )
+    .unwrap();
+
+    let body = body_re
+        .captures(content)
+        .map(|cap| String::from(&cap["body"]))
+        .unwrap_or_else(|| String::from(content));
+    let body = toc_entry_re.replace_all(&body, "<p$attrs><a href=\"$href\">$text</a></p>");
+
+    let mut writer = MarkdownWriter::default();
+    writer.write(&body);
+    writer.finish()
+}
+
+// Inline markup opened but not closed yet: the position of its marker in the inline text
+struct OpenInline {
+    tag: String,
+    position: usize,
+    href: Option<String>,
+}
+
+#[derive(Default)]
+struct TableState {
+    start: usize,
+    depth: usize,
+    has_merged_cells: bool,
+    rows: Vec<Vec<String>>,
+    cell: Option<String>,
+}
+
+#[derive(Default)]
+struct MarkdownWriter {
+    blocks: Vec<String>,
+    inline: String,
+    open_inlines: Vec<OpenInline>,
+    heading: Option<(usize, Option<String>)>,
+    // Ordered flag and whether the current item already has its marker
+    lists: Vec<(bool, bool)>,
+    pre: Option<String>,
+    table: Option<TableState>,
+    skipped: Option<(String, usize)>,
+    last_block_in_list: bool,
+}
+
+impl MarkdownWriter {
+    fn write(&mut self, html: &str) {
+        for (range, token) in html_tokens::tokenize(html) {
+            match token {
+                Token::Text(text) => {
+                    if self.skipped.is_none() {
+                        self.write_text(text);
+                    }
+                }
+                Token::Open { name, attrs } => self.open_tag(&name, attrs, range.start),
+                Token::Close { name } => self.close_tag(&name, range.end, html),
+                Token::Comment => {}
+            }
+        }
+    }
+
+    fn finish(mut self) -> String {
+        self.flush();
+        let mut result = self.blocks.join("\n\n");
+        result.push('\n');
+        result
+    }
+
+    fn write_text(&mut self, text: &str) {
+        if let Some(pre) = &mut self.pre {
+            pre.push_str(&html_text::unescape(text));
+            return;
+        }
+
+        static WHITESPACE_RE: OnceLock<Regex> = OnceLock::new();
+        let whitespace_re = WHITESPACE_RE.get_or_init(|| Regex::new(r#"\s+"#).unwrap());
+        let text = whitespace_re.replace_all(text, " ");
+        let text = if self.inline.is_empty() || self.inline.ends_with(' ') {
+            text.trim_start()
+        } else {
+            &text
+        };
+        self.inline.push_str(&escape_text(text));
+    }
+
+    fn open_tag(&mut self, name: &str, attrs: &str, position: usize) {
+        if let Some((skipped_tag, depth)) = &mut self.skipped {
+            if skipped_tag == name {
+                *depth += 1;
+            }
+            return;
+        }
+        if SKIPPED_TAGS.contains(&name) || attribute(attrs, "class") == Some("reference-controls") {
+            self.skipped = Some((String::from(name), 1));
+            return;
+        }
+
+        if let Some(table) = &mut self.table {
+            if name == "table" {
+                table.depth += 1;
+            }
+            if name == "td" || name == "th" {
+                let merged = |attr| attribute(attrs, attr).is_some_and(|span| span != "1");
+                table.has_merged_cells |= merged("colspan") || merged("rowspan");
+            }
+        }
+
+        if is_block(name) {
+            self.flush();
+        }
+
+        if let Some(level) = html_tokens::heading_level(name) {
+            // Markdown has six levels, deeper annex headings get the last one
+            self.heading = Some((level.min(6), attribute(attrs, "id").map(String::from)));
+        }
+        match name {
+            "ul" | "ol" => self.lists.push((name == "ol", false)),
+            "li" => {
+                if let Some(list) = self.lists.last_mut() {
+                    list.1 = false;
+                }
+            }
+            "pre" => self.pre = Some(String::new()),
+            "table" if self.table.is_none() => {
+                self.table = Some(TableState {
+                    start: position,
+                    depth: 1,
+                    ..TableState::default()
+                })
+            }
+            "tr" => {
+                if let Some(table) = &mut self.table {
+                    table.rows.push(Vec::new());
+                }
+            }
+            "td" | "th" => {
+                if let Some(table) = &mut self.table {
+                    table.cell = Some(String::new());
+                }
+            }
+            "br" => self
+                .inline
+                .push_str(if self.table.is_some() { "<br>" } else { "  \n" }),
+            "img" => {
+                if let Some(src) = attribute(attrs, "src") {
+                    let alt = attribute(attrs, "alt").unwrap_or_default();
+                    self.inline.push_str(&format!("![{}]({})", alt, src));
+                }
+            }
+            "a" => {
+                let href = attribute(attrs, "href").map(markdown_href);
+                if let Some(id) = attribute(attrs, "id") {
+                    self.inline.push_str(&format!("<a id=\"{}\"></a>", id));
+                }
+                self.open_inlines.push(OpenInline {
+                    tag: String::from(name),
+                    position: self.inline.len(),
+                    href,
+                });
+            }
+            "b" | "strong" | "i" | "em" => {
+                if let Some(id) = attribute(attrs, "id") {
+                    self.inline.push_str(&format!("<a id=\"{}\"></a>", id));
+                }
+                self.open_inlines.push(OpenInline {
+                    tag: String::from(name),
+                    position: self.inline.len(),
+                    href: None,
+                });
+            }
+            "sub" | "sup" => self.inline.push_str(&format!("<{}>", name)),
+            _ => {}
+        }
+    }
+
+    fn close_tag(&mut self, name: &str, position: usize, html: &str) {
+        if let Some((skipped_tag, depth)) = &mut self.skipped {
+            if skipped_tag == name {
+                *depth -= 1;
+                if *depth == 0 {
+                    self.skipped = None;
+                }
+            }
+            return;
+        }
+
+        match name {
+            "a" | "b" | "strong" | "i" | "em" => self.close_inline(name),
+            "sub" | "sup" => self.inline.push_str(&format!("</{}>", name)),
+            _ => {}
+        }
+
+        if is_block(name) {
+            self.flush();
+        }
+
+        if html_tokens::heading_level(name).is_some() {
+            self.heading = None;
+        }
+        match name {
+            "ul" | "ol" => {
+                self.lists.pop();
+            }
+            "pre" => {
+                if let Some(pre) = self.pre.take() {
+                    self.push_block(format!("```\n{}\n```", pre.trim_matches('\n')));
+                }
+            }
+            "td" | "th" => {
+                if let Some(table) = &mut self.table {
+                    if let Some(cell) = table.cell.take() {
+                        if let Some(row) = table.rows.last_mut() {
+                            row.push(cell);
+                        }
+                    }
+                }
+            }
+            "table" => {
+                let finished = match &mut self.table {
+                    Some(table) => {
+                        table.depth -= 1;
+                        table.depth == 0
+                    }
+                    None => false,
+                };
+                if finished {
+                    let table = self.table.take().unwrap();
+                    let block = if table.has_merged_cells {
+                        clean_html_table(&html[table.start..position])
+                    } else {
+                        render_pipe_table(&table.rows)
+                    };
+                    self.push_block(block);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn close_inline(&mut self, name: &str) {
+        let index = match self.open_inlines.iter().rposition(|open| open.tag == name) {
+            Some(index) => index,
+            None => return,
+        };
+        let open = self.open_inlines.remove(index);
+        if open.position > self.inline.len() {
+            return;
+        }
+
+        let inner = self.inline.split_off(open.position);
+        let trimmed = inner.trim();
+        if trimmed.is_empty() {
+            self.inline.push_str(&inner);
+            return;
+        }
+
+        let (begin, end) = match (name, &open.href) {
+            ("a", Some(href)) => (String::from("["), format!("]({})", href)),
+            ("a", None) => (String::new(), String::new()),
+            ("b", _) | ("strong", _) => (String::from("**"), String::from("**")),
+            _ => (String::from("*"), String::from("*")),
+        };
+        if inner.starts_with(char::is_whitespace) {
+            self.inline.push(' ');
+        }
+        self.inline
+            .push_str(&format!("{}{}{}", begin, trimmed, end));
+        if inner.ends_with(char::is_whitespace) {
+            self.inline.push(' ');
+        }
+    }
+
+    // Ends the current paragraph, heading, list item or table cell
+    fn flush(&mut self) {
+        self.open_inlines.clear();
+        let text = std::mem::take(&mut self.inline);
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+
+        if let Some(table) = &mut self.table {
+            if let Some(cell) = &mut table.cell {
+                if !cell.is_empty() {
+                    cell.push_str("<br>");
+                }
+                cell.push_str(text);
+            }
+            return;
+        }
+        let text = &escape_line_starts(text);
+
+        if let Some((level, id)) = &self.heading {
+            let anchor = id
+                .as_ref()
+                .map(|id| format!("<a id=\"{}\"></a>", id))
+                .unwrap_or_default();
+            let block = format!("{} {}{}", "#".repeat(*level), anchor, text);
+            self.push_block(block);
+            return;
+        }
+
+        if let Some((ordered, has_marker)) = self.lists.last().cloned() {
+            let indent = "  ".repeat(self.lists.len() - 1);
+            let marker = if has_marker {
+                String::from("  ")
+            } else if ordered {
+                String::from("1. ")
+            } else {
+                String::from("- ")
+            };
+            self.lists.last_mut().unwrap().1 = true;
+            self.push_block(format!("{}{}{}", indent, marker, text));
+            return;
+        }
+
+        // NOTE, NOTE 1 and Editor's Note paragraphs
+        static NOTE_RE: OnceLock<Regex> = OnceLock::new();
+        let note_re = NOTE_RE
+            .get_or_init(|| Regex::new(r#"^(NOTE(\s+\d+)?|Editor(')?s\s+[Nn]ote)\s*:"#).unwrap());
+        if note_re.is_match(text) {
+            self.push_block(format!("> {}", text.replace('\n', "\n> ")));
+        } else {
+            self.push_block(String::from(text));
+        }
+    }
+
+    fn push_block(&mut self, block: String) {
+        // Items of a list are not separated by empty lines
+        let is_list_item = !self.lists.is_empty();
+        match self.blocks.last_mut() {
+            Some(last) if is_list_item && self.last_block_in_list => {
+                last.push('\n');
+                last.push_str(&block);
+            }
+            _ => self.blocks.push(block),
+        }
+        self.last_block_in_list = is_list_item;
+    }
+}
+
+// "../23.502/23.502.html#4.3" becomes "../23.502/23.502.md#4.3"
+fn markdown_href(href: &str) -> String {
+    static DOCUMENT_RE: OnceLock<Regex> = OnceLock::new();
+    let document_re = DOCUMENT_RE.get_or_init(|| {
+        Regex::new(r#"^\.\./(?P<ts_no>[^/]+)/(?P<file>[^/#]+)\.html(?P<rest>#.*)?$"#).unwrap()
+    });
+
+    match document_re.captures(href) {
+        Some(cap) if cap["file"] == cap["ts_no"] => format!(
+            "../{0}/{0}.md{1}",
+            &cap["ts_no"],
+            cap.name("rest").map_or("", |rest| rest.as_str())
+        ),
+        _ => String::from(href),
+    }
+}
+
+fn is_block(name: &str) -> bool {
+    BLOCK_TAGS.contains(&name) || html_tokens::heading_level(name).is_some()
+}
+
+// Text is still escaped HTML, a "<" left in it would start a tag
+fn escape_text(text: &str) -> String {
+    static SPECIAL_RE: OnceLock<Regex> = OnceLock::new();
+    let special_re = SPECIAL_RE.get_or_init(|| Regex::new(r#"([\\`*_\[\]])"#).unwrap());
+    String::from(special_re.replace_all(text, "\\$1")).replace('<', "&lt;")
+}
+
+// "# foo", "- foo", "> foo" and "1. foo" at the start of a line would be headings, lists or
+// quotes
+fn escape_line_starts(text: &str) -> String {
+    static MARKER_RE: OnceLock<Regex> = OnceLock::new();
+    static NUMBER_RE: OnceLock<Regex> = OnceLock::new();
+    let marker_re = MARKER_RE
+        .get_or_init(|| Regex::new(r#"(?m)^(?P<indent> {0,3})(?P<marker>>|[#+-](\s|$))"#).unwrap());
+    let number_re = NUMBER_RE
+        .get_or_init(|| Regex::new(r#"(?m)^(?P<number> {0,3}\d+)(?P<marker>[.)](\s|$))"#).unwrap());
+
+    let text = marker_re.replace_all(text, "${indent}\\${marker}");
+    String::from(number_re.replace_all(&text, "${number}\\${marker}"))
+}
+
+fn render_pipe_table(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    if columns == 0 {
+        return String::new();
+    }
+
+    let render_row = |row: &Vec<String>| {
+        let mut cells = row
+            .iter()
+            .map(|cell| cell.replace('|', "\\|").replace('\n', "<br>"))
+            .collect::<Vec<_>>();
+        cells.resize(columns, String::new());
+        format!("| {} |", cells.join(" | "))
+    };
+
+    let mut lines = Vec::new();
+    let mut rows = rows.iter().filter(|row| !row.is_empty());
+    if let Some(header) = rows.next() {
+        lines.push(render_row(header));
+        lines.push(format!("|{}", " --- |".repeat(columns)));
+    }
+    lines.extend(rows.map(render_row));
+    lines.join("\n")
+}
+
+// Keeps only the attributes describing the table structure, links and anchors
+fn clean_html_table(html: &str) -> String {
+    static TAG_RE: OnceLock<Regex> = OnceLock::new();
+    static FONT_RE: OnceLock<Regex> = OnceLock::new();
+    static WHITESPACE_RE: OnceLock<Regex> = OnceLock::new();
+    let tag_re = TAG_RE.get_or_init(|| {
+        Regex::new(r#"<(?P<close>/?)(?P<name>[a-zA-Z][a-zA-Z0-9]*)(?P<attrs>[^>]*)>"#).unwrap()
+    });
+    let font_re = FONT_RE.get_or_init(|| Regex::new(r#"</?(font|span)>"#).unwrap());
+    let whitespace_re = WHITESPACE_RE.get_or_init(|| Regex::new(r#"\s*\n\s*"#).unwrap());
+    let kept = ["colspan", "rowspan", "href", "id", "src", "alt"];
+
+    let result = tag_re.replace_all(html, |cap: &Captures| {
+        let name = cap["name"].to_lowercase();
+        if !cap["close"].is_empty() {
+            return format!("</{}>", name);
+        }
+
+        let attrs = kept
+            .iter()
+            .filter_map(|attr| {
+                attribute(&cap["attrs"], attr).map(|value| {
+                    let value = if *attr == "href" {
+                        markdown_href(value)
+                    } else {
+                        String::from(value)
+                    };
+                    format!(" {}=\"{}\"", attr, value)
+                })
+            })
+            .collect::<String>();
+        format!("<{}{}>", name, attrs)
+    });
+
+    let result = font_re.replace_all(&result, "");
+    String::from(whitespace_re.replace_all(&result, "\n"))
+}
+
+#[test]
+fn test_html_to_markdown_headings_and_paragraphs() {
+    let source = r##"<html><head><title>Foo</title></head><body lang="en-US">
+<h1 id="4"  class="western">4	General<span class="reference-controls"><a class="permalink" href="#4"></a><button class="copy-reference">x</button></span></h1>
+<p class="western">The  <b>AMF</b> uses <a href="../23.502/23.502.html#4.3">TS 23.502 clause 4.3</a>
+and <a href="#5">clause 5</a>, see <i>Nsmf_PDUSession </i>service.</p>
+<p class="western"><a name="foo"></a></p>
+<p class="western">NOTE 1:	Lorem ipsum.</p>
+<p class="western"><b id="4.1-1">Figure 4.1-1: Bar</b></p>
+<p><img src="23501_files/image1.png" alt=""/></p>
+</body></html>"##;
+    let expected = r#"# <a id="4"></a>4 General
+
+The **AMF** uses [TS 23.502 clause 4.3](../23.502/23.502.md#4.3) and [clause 5](#5), see *Nsmf\_PDUSession* service.
+
+> NOTE 1: Lorem ipsum.
+
+<a id="4.1-1"></a>**Figure 4.1-1: Bar**
+
+![](23501_files/image1.png)
+"#;
+
+    assert_eq!(html_to_markdown(source), expected);
+}
+
+#[test]
+fn test_html_to_markdown_annex_headings_and_special_text() {
+    let source = r#"<h8 id="A.1.1">A.1.1	Foo</h8>
+<p>- The &lt;NF type&gt; &amp; # of &lt;b&gt;<br>1. Bar<br># 2</p><p>4.2.1 and -1 dBm</p>"#;
+    let expected = "###### <a id=\"A.1.1\"></a>A.1.1 Foo\n\n\\- The &lt;NF type&gt; &amp; # of &lt;b&gt;  \n1\\. Bar  \n\\# 2\n\n4.2.1 and -1 dBm\n";
+
+    assert_eq!(html_to_markdown(source), expected);
+}
+
+#[test]
+fn test_html_to_markdown_lists_and_code() {
+    let source = r#"<ul><li><p>Foo</p></li><li>Bar<ul><li>Baz</li></ul></li></ul><p>Qux</p><pre>a  &lt;b&gt;
+  c</pre>"#;
+    let expected = "- Foo\n- Bar\n  - Baz\n\nQux\n\n```\na  <b>\n  c\n```\n";
+
+    assert_eq!(html_to_markdown(source), expected);
+}
+
+#[test]
+fn test_html_to_markdown_tables() {
+    let source = r#"<table width="100%" border="1"><tr><td style="x"><p>A</p></td><td><p>B|C</p><p>D</p></td></tr>
+<tr><td><p>1</p></td><td><p>2</p></td></tr></table>
+<table><tr><td colspan="2"><font face="Arial">A</font></td></tr><tr><td>1</td><td><a href="../23.501/23.501.html">2</a></td></tr></table>"#;
+    let expected = r#"| A | B\|C<br>D |
+| --- | --- |
+| 1 | 2 |
+
+<table><tr><td colspan="2">A</td></tr><tr><td>1</td><td><a href="../23.501/23.501.md">2</a></td></tr></table>
+"#;
+
+    assert_eq!(html_to_markdown(source), expected);
+}
+
+#[test]
+fn test_markdown_href() {
+    assert_eq!(markdown_href("#4.1"), "#4.1");
+    assert_eq!(
+        markdown_href("../23.501/23.501.html#table-4.1-1"),
+        "../23.501/23.501.md#table-4.1-1"
+    );
+    assert_eq!(markdown_href("../index.html"), "../index.html");
+}
@@ -0,0 +1,125 @@
+use crate::html_text;
+use crate::html_tokens;
+
+use failure::ResultExt;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+pub const PREVIEWS_FILE_NAME: &str = "previews.js";
+const MAX_TEXT_LENGTH: usize = 400;
+
+// What a popup shows for a link to a clause, table or figure
+#[derive(Serialize, Debug, PartialEq)]
+struct Preview {
+    heading: String,
+    text: String,
+}
+
+// Writes the previews of every clause, table and figure of a document as a script, so pages of
+// other documents can load it from the disk, where fetching JSON is not allowed
+pub fn save_previews(output_dir: &str, ts_no: &str, content: &str) -> Result<(), failure::Error> {
+    println!("\tSaving previews...");
+
+    let path = format!("{}/{}", output_dir, PREVIEWS_FILE_NAME);
+    let previews = serde_json::to_string(&extract_previews(content))?;
+    let script = format!(
+        "(window.TS_PREVIEWS = window.TS_PREVIEWS || {{}})[{}] = {};\n",
+        serde_json::to_string(ts_no)?,
+        previews
+    );
+    std::fs::write(&path, script)
+        .with_context(|_| format!("could not write previews file `{}`", path))?;
+
+    Ok(())
+}
+
+// Heading and first paragraph of every clause, caption of every table and figure, keyed by id
+fn extract_previews(content: &str) -> BTreeMap<String, Preview> {
+    let heading_re = Regex::new(html_tokens::HEADING_WITH_ID_PATTERN).unwrap();
+    let paragraph_re = Regex::new(r#"(?is)<p[\s>].*?</p>"#).unwrap();
+    let caption_re =
+        Regex::new(r#"(?s)<b id="(?P<id>[^"]+)">(?P<caption>\s*(Figure|Table)\s.*?)</b>"#).unwrap();
+
+    let mut previews = BTreeMap::new();
+
+    let headings = heading_re.captures_iter(content).collect::<Vec<_>>();
+    for (i, cap) in headings.iter().enumerate() {
+        let text_begin = cap.get(0).unwrap().end();
+        let text_end = headings
+            .get(i + 1)
+            .map(|next| next.get(0).unwrap().start())
+            .unwrap_or_else(|| content.len());
+
+        let text = paragraph_re
+            .find_iter(&content[text_begin..text_end])
+            .map(|paragraph| html_text::to_plain_text(paragraph.as_str()))
+            .find(|text| !text.is_empty())
+            .unwrap_or_default();
+
+        previews.insert(
+            String::from(&cap["id"]),
+            Preview {
+                heading: html_text::to_plain_text(&cap["heading"]),
+                text: shorten(&text),
+            },
+        );
+    }
+
+    for cap in caption_re.captures_iter(content) {
+        previews.insert(
+            String::from(&cap["id"]),
+            Preview {
+                heading: html_text::to_plain_text(&cap["caption"]),
+                text: String::new(),
+            },
+        );
+    }
+
+    previews
+}
+
+fn shorten(text: &str) -> String {
+    if text.chars().count() <= MAX_TEXT_LENGTH {
+        return String::from(text);
+    }
+
+    let mut result = text.chars().take(MAX_TEXT_LENGTH).collect::<String>();
+    if let Some(last_space) = result.rfind(' ') {
+        result.truncate(last_space);
+    }
+    result.push('…');
+    result
+}
+
+#[test]
+fn test_extract_previews() {
+    let source = r#"<h1 id="4"  class="western">4	General<span class="reference-controls"><a class="permalink" href="x"></a></span></h1>
+<p class="western"><a name="y"></a></p>
+<p class="western">Lorem <b>ipsum</b>.</p><p>Dolor</p>
+<h2 id="4.1"  class="western">4.1	Foo</h2>
+<p><b id="table-4.1-1">Table 4.1-1: Bar</b></p>
+<h2 id="4.2"  class="western">4.2	Baz</h2>"#;
+
+    let previews = extract_previews(source);
+    let preview = |id: &str| {
+        let preview = &previews[id];
+        (preview.heading.as_str(), preview.text.as_str())
+    };
+
+    assert_eq!(previews.len(), 4);
+    assert_eq!(preview("4"), ("4 General", "Lorem ipsum."));
+    assert_eq!(preview("4.1"), ("4.1 Foo", "Table 4.1-1: Bar"));
+    assert_eq!(preview("table-4.1-1"), ("Table 4.1-1: Bar", ""));
+    assert_eq!(preview("4.2"), ("4.2 Baz", ""));
+}
+
+#[test]
+fn test_shorten() {
+    assert_eq!(shorten("Lorem ipsum"), "Lorem ipsum");
+
+    let long_text = "Lorem ipsum ".repeat(40);
+    let short_text = shorten(&long_text);
+    assert!(short_text.chars().count() <= MAX_TEXT_LENGTH + 1);
+    assert!(short_text.ends_with("ipsum…"));
+}
@@ -0,0 +1,59 @@
+use failure::ResultExt;
+use serde::{Deserialize, Serialize};
+
+pub const REFERENCES_FILE_NAME: &str = "references.json";
+
+// A link to a clause or a document inserted by the clause links pass
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ClauseReference {
+    // Clause the reference is written in, none before the first clause heading
+    pub source_clause: Option<String>,
+    pub target_ts_no: String,
+    // None when the link is to the whole document, "TS 23.501 [2]"
+    pub target_clause: Option<String>,
+}
+
+// References written in one document, stored next to its HTML file
+#[derive(Serialize, Deserialize)]
+pub struct DocumentReferences {
+    pub ts_no: String,
+    pub references: Vec<ClauseReference>,
+}
+
+pub fn save_references(
+    output_dir: &str,
+    document_references: &DocumentReferences,
+) -> Result<(), failure::Error> {
+    let path = format!("{}/{}", output_dir, REFERENCES_FILE_NAME);
+    let content = serde_json::to_string_pretty(document_references)?;
+    std::fs::write(&path, content)
+        .with_context(|_| format!("could not write references file `{}`", path))?;
+
+    Ok(())
+}
+
+// References of every document in the output directory, also of the ones converted by
+// previous runs
+pub fn load_all_references(
+    out_path: &std::path::Path,
+) -> Result<Vec<DocumentReferences>, failure::Error> {
+    let mut all_references = Vec::new();
+
+    for entry in std::fs::read_dir(out_path)
+        .with_context(|_| format!("could not read output directory `{}`", out_path.display()))?
+    {
+        let path = entry?.path().join(REFERENCES_FILE_NAME);
+        if !path.is_file() {
+            continue;
+        }
+
+        let content = std::fs::read_to_string(&path)
+            .with_context(|_| format!("could not read references file `{}`", path.display()))?;
+        let document_references: DocumentReferences = serde_json::from_str(&content)
+            .with_context(|_| format!("could not parse references file `{}`", path.display()))?;
+        all_references.push(document_references);
+    }
+
+    all_references.sort_by(|a, b| a.ts_no.cmp(&b.ts_no));
+    Ok(all_references)
+}
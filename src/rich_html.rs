@@ -1,20 +1,110 @@
-#[path = "source_modifier.rs"]
-mod source_modifier;
-#[path = "source_parser.rs"]
-mod source_parser;
-
-use regex::Regex;
-
-pub fn enrich_html(content: &String) -> String {
+// The tests borrow their sources, as they were first written
+#![cfg_attr(test, allow(clippy::needless_borrow, clippy::nonminimal_bool))]
+
+use crate::asn1;
+use crate::clause_index::ClauseIndex;
+use crate::code_annexes;
+use crate::glossary;
+use crate::glossary::DefinedTerm;
+use crate::highlight;
+use crate::html_tokens;
+use crate::link_rules;
+use crate::link_rules::{CompiledRules, LinkRules};
+use crate::linkable_text;
+use crate::references::ClauseReference;
+use crate::source_modifier;
+use crate::source_parser;
+use crate::spec_metadata::SpecMetadata;
+use crate::toc;
+
+use regex::{Captures, Regex};
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+pub fn enrich_html(
+    content: &str,
+    metadata: &SpecMetadata,
+    clause_index: &ClauseIndex,
+    link_rules: &LinkRules,
+    terms: &[DefinedTerm],
+) -> (String, Vec<ClauseReference>) {
+    let ts_no = metadata.ts_no.as_str();
     let mut result = remove_hard_spaces(content);
     result = remove_span_language_en(&result);
-    result = better_toc(&result);
+    result = asn1::render_asn1_blocks(&result);
+    result = code_annexes::render_code_annexes(&result);
+    result = highlight::highlight_code_blocks(&result);
     result = add_clause_ids(&result);
-    result = add_clause_links(&result);
+    result = glossary::add_term_anchors(&result);
+    result = better_toc(&result);
+    let (linked, references) = add_clause_links(&result, ts_no, clause_index, link_rules);
+    result = linked;
     result = add_figure_ids(&result);
-    result = add_figure_links(&result);
+    result = add_table_ids(&result);
+    result = add_reference_controls(&result, metadata);
+    result = add_figure_links(&result, ts_no, link_rules);
+    result = asn1::add_asn1_type_links(&result);
+    result = code_annexes::add_data_type_links(&result);
+    result = glossary::add_term_tooltips(&result, terms);
+
+    (result, references)
+}
 
-    result
+// Puts a permalink and a "copy reference" button into every clause heading and after every table
+// and figure caption. The buttons have no text, the theme draws them, so they are not part of
+// the heading text.
+fn add_reference_controls(content: &str, metadata: &SpecMetadata) -> String {
+    println!("\tAdding reference controls...");
+
+    let heading_re = Regex::new(html_tokens::HEADING_WITH_ID_PATTERN).unwrap();
+    let caption_re = Regex::new(r#"(?s)<b id="(?P<id>[^"]+)">\s*(Figure|Table)\s.*?</b>"#).unwrap();
+
+    let result = heading_re.replace_all(content, |cap: &Captures| {
+        let id = &cap["id"];
+        let target = if id.len() <= 2 && id.chars().all(|c| c.is_ascii_uppercase()) {
+            format!("Annex {}", id)
+        } else {
+            format!("clause {}", id)
+        };
+        // The controls go at the end of the heading content, before the closing tag
+        let heading_end = cap.name("heading").unwrap().end() - cap.get(0).unwrap().start();
+        let (heading, end) = cap[0].split_at(heading_end);
+        format!(
+            "{}{}{}",
+            heading,
+            render_reference_controls(id, &target, metadata),
+            end
+        )
+    });
+
+    let result = caption_re.replace_all(&result, |cap: &Captures| {
+        let id = &cap["id"];
+        let target = match id.strip_prefix("table-") {
+            Some(table_no) => format!("Table {}", table_no),
+            None => format!("Figure {}", id),
+        };
+        format!(
+            "{}{}",
+            &cap[0],
+            render_reference_controls(id, &target, metadata)
+        )
+    });
+
+    String::from(result)
+}
+
+// "TS 23.502 v17.4.0 clause 4.3.2.2.1", the script of the page adds the URL when copying
+fn render_reference_controls(id: &str, target: &str, metadata: &SpecMetadata) -> String {
+    let mut reference = format!("TS {}", metadata.ts_no);
+    if !metadata.version.is_empty() {
+        reference.push_str(&format!(" v{}", metadata.version));
+    }
+    reference.push_str(&format!(" {}", target));
+
+    format!(
+        "<span class=\"reference-controls\"><a class=\"permalink\" href=\"#{0}\" title=\"Permalink\"></a><button class=\"copy-reference\" type=\"button\" title=\"Copy reference\" data-reference=\"{1}\" data-target=\"{0}\"></button></span>",
+        id, reference
+    )
 }
 
 fn add_figure_ids(content: &str) -> String {
@@ -25,126 +115,233 @@ fn add_figure_ids(content: &str) -> String {
     String::from(re.replace_all(content, "<b id=\"$figure_no\">$content"))
 }
 
-fn add_figure_links(content: &str) -> String {
-    println!("\tAdding figure links...");
-
+fn add_table_ids(content: &str) -> String {
+    println!("\tAdding table ids...");
     let re =
-        Regex::new(r#"(?s:(?P<content>([Ff]igure\s+(?P<figure_no>(\d[\.\d\-a-z]*-\d+))))[^:])"#)
-            .unwrap();
+        Regex::new(r#"(?s:<b>(?P<content>(\s*Table\s+(?P<table_no>(\d[\.\d\-a-z]*)):)))"#).unwrap();
 
-    String::from(re.replace_all(content, "<a href=\"#$figure_no\">$content</a> "))
+    String::from(re.replace_all(content, "<b id=\"table-$table_no\">$content"))
 }
 
-fn add_clause_links(content: &str) -> String {
+fn add_figure_links(content: &str, ts_no: &str, link_rules: &LinkRules) -> String {
+    println!("\tAdding figure links...");
+
+    // Figure and table rules have no clause groups, so neither of these is used
+    let clause_no_re = Regex::new(link_rules::CLAUSE_NO).unwrap();
+    let clause_index = ClauseIndex::new();
+    let mut references = Vec::new();
+
+    linkable_text::replace_linkable_text(content, |text, current_clause| {
+        link_references(
+            text,
+            &link_rules.figure_rules,
+            &clause_no_re,
+            (ts_no, current_clause),
+            &clause_index,
+            &mut references,
+        )
+    })
+}
+
+// Links to clauses missing from their (known) target document are not inserted, so "in 3.5 seconds"
+// stays plain text. Every inserted clause link is also returned as a reference.
+fn add_clause_links(
+    content: &str,
+    ts_no: &str,
+    clause_index: &ClauseIndex,
+    link_rules: &LinkRules,
+) -> (String, Vec<ClauseReference>) {
     println!("\tAdding clause links...");
 
-    let regexes = vec![
-        // TS 23.501 [2], clause 5.4.4.1b // the comma is optional, the last letter is optional
-        r#"(TS\s+)?(?P<ts_no_0>(\d{2}\.\d{3}))\s+\[\d+\],?\s+[cC]lause\s+(?P<clause_no_0>(\d[\.\da-z]*[\da-z]))"#,
-        // clause 5.3.3.1 (Some text) in TS 23.401 [13] // "(Some text)" is optional, "in" can be "of"
-        r#"(((in)|(see))\s+)?[cC]lause\s+(?P<clause_no_1>(\d[\.\da-z]*[\da-z]))\s+(\([^<^>.]+\)\s+)?((of)|(in))\s+TS\s+(?P<ts_no_1>(\d{2}\.\d{3}))\s+\[\d+\]"#,
-        // in clause 4.4 // "in" can be "see" and it is optional
-        r#"(((in)|(see))\s+)?[cC]lause\s+(?P<clause_no_2>(\d[\.\da-z]*[\da-z]))"#,
-        // in 4.3.3.2 // "in" can be "see" and it is mandatory
-        r#"((in)|(see))\s+(?P<clause_no_3>(\d[\.\da-z]*[\da-z]))"#,
-        // TS 23.501 [2]
-        r#"(TS\s+)?(?P<ts_no_2>(\d{2}\.\d{3}))\s+\[\d+\]"#,
-    ];
+    let clause_no_re = Regex::new(link_rules::CLAUSE_NO).unwrap();
+    let mut references = Vec::new();
+
+    let result = linkable_text::replace_linkable_text(content, |text, current_clause| {
+        link_references(
+            text,
+            &link_rules.clause_rules,
+            &clause_no_re,
+            (ts_no, current_clause),
+            clause_index,
+            &mut references,
+        )
+    });
+
+    (result, references)
+}
 
-    let joined = regexes.join(")|(");
-    let complete_regex = format!("(?s:(?P<whole_content>(({}))))", joined);
-    let re = Regex::new(complete_regex.as_str()).unwrap();
+// `current` is the TS number of the document and the clause the text is in
+fn link_references(
+    text: &str,
+    rules: &CompiledRules,
+    clause_no_re: &Regex,
+    current: (&str, Option<&str>),
+    clause_index: &ClauseIndex,
+    references: &mut Vec<ClauseReference>,
+) -> String {
+    let (current_ts_no, current_clause) = current;
+    let mut add_reference = |target_ts_no: &str, target_clause: Option<&str>| {
+        references.push(ClauseReference {
+            source_clause: current_clause.map(String::from),
+            target_ts_no: String::from(target_ts_no),
+            target_clause: target_clause.map(String::from),
+        })
+    };
 
     let mut result = String::new();
     let mut last_end = 0;
 
-    for cap in re.captures_iter(content) {
+    for cap in rules.captures_iter(text) {
         if let Some(whole_match) = cap.get(0) {
-            let whole_content = &content[whole_match.start()..whole_match.end()];
+            let whole_text = &text[whole_match.start()..whole_match.end()];
 
-            result.push_str(&content[last_end..whole_match.start()]);
+            result.push_str(&text[last_end..whole_match.start()]);
 
             last_end = whole_match.end();
 
-            let mut result_link = String::new();
+            let mut document_link = String::new();
+            let mut target_ts_no = current_ts_no;
+            if let Some(ts_no) = rules.group(&cap, "ts_no") {
+                document_link.push_str(&format!("../{0}/{0}.html", ts_no.as_str()));
+                target_ts_no = ts_no.as_str();
+            }
 
-            let ts_getter = || {
-                let number_of_ts_regexes = 3;
-                for name_no in 0..number_of_ts_regexes {
-                    let group_name = format!("ts_no_{}", name_no);
-                    if let Some(clause_no) = cap.name(&group_name) {
-                        return Some(clause_no);
-                    }
+            if let Some(clause_list) = rules.group(&cap, "clause_list") {
+                // Every clause number of the list gets its own link, so does the TS reference
+                let mut links = clause_no_re
+                    .find_iter(clause_list.as_str())
+                    .filter(|clause_no| clause_index.may_contain(target_ts_no, clause_no.as_str()))
+                    .map(|clause_no| {
+                        add_reference(target_ts_no, Some(clause_no.as_str()));
+                        (
+                            clause_list.start() + clause_no.start(),
+                            clause_list.start() + clause_no.end(),
+                            format!("{}#{}", document_link, clause_no.as_str()),
+                        )
+                    })
+                    .collect::<Vec<_>>();
+
+                if let Some(ts_ref) = rules.group(&cap, "ts_ref") {
+                    add_reference(target_ts_no, None);
+                    links.push((ts_ref.start(), ts_ref.end(), document_link.clone()));
                 }
+                links.sort();
 
-                return None;
-            };
-            if let Some(ts_no) = ts_getter() {
-                result_link.push_str(&format!("../{0}/{0}.html", ts_no.as_str()));
+                let mut pos = whole_match.start();
+                for (start, end, href) in links {
+                    result.push_str(&text[pos..start]);
+                    result.push_str(&format!("<a href=\"{}\">{}</a>", href, &text[start..end]));
+                    pos = end;
+                }
+                result.push_str(&text[pos..whole_match.end()]);
+                continue;
             }
 
-            let clause_getter = || {
-                for name_no in 0..regexes.len() {
-                    let group_name = format!("clause_no_{}", name_no);
-                    if let Some(clause_no) = cap.name(&group_name) {
-                        return Some(clause_no);
-                    }
+            let mut result_link = document_link;
+            let mut target_clause = None;
+            if let Some(clause_no) = rules.group(&cap, "clause_no") {
+                // A missing clause of another document still links to the document
+                if clause_index.may_contain(target_ts_no, clause_no.as_str()) {
+                    result_link.push_str(&format!("#{}", clause_no.as_str()));
+                    target_clause = Some(clause_no.as_str());
+                } else if result_link.is_empty() {
+                    result.push_str(whole_text);
+                    continue;
                 }
+            }
 
-                return None;
-            };
+            let figure_no = rules
+                .group(&cap, "figure_no")
+                .map(|no| String::from(no.as_str()));
+            let table_no = rules
+                .group(&cap, "table_no")
+                .map(|no| format!("table-{}", no.as_str()));
+            if let Some(id) = figure_no.or(table_no) {
+                // "Figure 4.2-1: Foo" is the caption itself, not a reference to it
+                if text[whole_match.end()..].starts_with(':') {
+                    result.push_str(whole_text);
+                    continue;
+                }
+                result_link.push_str(&format!("#{}", id));
+            }
 
-            if let Some(clause_no) = clause_getter() {
-                result_link.push_str(&format!("#{}", clause_no.as_str()));
+            // A link to a document is a reference even without a clause
+            if target_clause.is_some() || rules.group(&cap, "ts_no").is_some() {
+                add_reference(target_ts_no, target_clause);
             }
 
             let to_insert = if !result_link.is_empty() {
-                format!("<a href=\"{}\">{}</a>", result_link, whole_content)
+                format!("<a href=\"{}\">{}</a>", result_link, whole_text)
             } else {
-                String::from(whole_content)
+                String::from(whole_text)
             };
 
             result.push_str(&to_insert);
         }
     }
-    result.push_str(&content[last_end..]);
+    result.push_str(&text[last_end..]);
     result
 }
 
+pub fn collect_clause_ids(content: &str) -> HashSet<String> {
+    println!("\tCollecting clause ids...");
+
+    let content = remove_span_language_en(&remove_hard_spaces(content));
+    let heading_re = Regex::new(HEADING).unwrap();
+
+    heading_re
+        .find_iter(&content)
+        .filter_map(|heading| extract_clause_no_from_h_entry(heading.as_str()))
+        .collect()
+}
+
+// <h1> to <h9> elements, not <head> or <hr>
+const HEADING: &str = r#"(?is)<h\d[\s>].*?</h\d>"#;
+
 fn add_clause_ids(content: &str) -> String {
     println!("\tAdding clause ids...");
 
-    let mut modifier = source_modifier::SourceModifier::new(&content);
+    let heading_re = Regex::new(HEADING).unwrap();
 
-    while modifier.is_before_end("<h") {
-        modifier.copy_til_begin_of("<h");
-
-        let h_content = modifier.get_content_til_end_of("</h").unwrap();
-        if let Some(clause_no) = extract_clause_no_from_h_entry(&h_content) {
-            modifier.copy_til_end_of("<h");
-            modifier.copy_chars_count(1);
-            modifier.push_str(&format!(" id=\"{}\" ", clause_no));
-            modifier.copy_til_end_of("</h");
-        } else {
-            modifier.copy_til_end_of("</h");
+    let mut result = String::new();
+    let mut copied_until = 0;
+    for heading in heading_re.find_iter(content) {
+        if let Some(clause_no) = extract_clause_no_from_h_entry(heading.as_str()) {
+            let name_end = heading.start() + "<h1".len();
+            result.push_str(&content[copied_until..name_end]);
+            result.push_str(&format!(" id=\"{}\" ", clause_no));
+            copied_until = name_end;
         }
     }
+    result.push_str(&content[copied_until..]);
 
-    modifier.copy_til_end_of_source();
-    modifier.get_result().clone()
+    result
 }
 
+const LIBREOFFICE_TOC_DIV: &str = r#"<div id="Table of Contents1" dir="ltr">"#;
+
+// Links the entries of the TOC LibreOffice generated from the TOC field. Documents without the
+// field get a TOC generated from their clause headings.
 fn better_toc(content: &str) -> String {
-    println!("\tTOC...");
-    let mut modifier = source_modifier::SourceModifier::new(&content);
+    if content.contains(LIBREOFFICE_TOC_DIV) {
+        println!("\tTOC: linking LibreOffice TOC...");
+        link_toc_entries(content)
+    } else {
+        println!("\tTOC: no LibreOffice TOC, generating from headings...");
+        generate_toc(content)
+    }
+}
 
-    modifier.copy_til_end_of(r#"<div id="Table of Contents1" dir="ltr">"#);
+fn link_toc_entries(content: &str) -> String {
+    let mut modifier = source_modifier::SourceModifier::new(content);
+
+    modifier.copy_til_end_of(LIBREOFFICE_TOC_DIV);
 
     while modifier.is_a_before_b("<p", "</div>") {
         modifier.copy_til_begin_of("<p");
 
         let p_content = modifier.get_content_til_end_of("</p>").unwrap();
-        if let Some(clause_no) = extract_clause_no_from_toc_entry(&p_content) {
+        if let Some(clause_no) = extract_clause_no_from_toc_entry(p_content) {
             modifier.push_str(&format!("<a href=\"#{}\">", clause_no));
             modifier.copy_til_end_of("</p>");
             modifier.push_str("</a>");
@@ -157,8 +354,27 @@ fn better_toc(content: &str) -> String {
     modifier.get_result().clone()
 }
 
+// Puts the TOC right before the first clause heading, after the cover page and foreword
+fn generate_toc(content: &str) -> String {
+    let entries = toc::collect_toc(content);
+    let first_heading_re = Regex::new(r#"<h\d\s[^>]*\bid=""#).unwrap();
+
+    match first_heading_re.find(content) {
+        Some(first_heading) if !entries.is_empty() => format!(
+            "{}<div id=\"toc\" class=\"generated-toc\"><p class=\"toc-title\">Contents</p>{}</div>\n{}",
+            &content[..first_heading.start()],
+            toc::render_toc_list(&entries, false),
+            &content[first_heading.start()..]
+        ),
+        _ => {
+            println!("\tTOC: no clause headings, skipped");
+            String::from(content)
+        }
+    }
+}
+
 fn extract_clause_no_from_h_entry(h_entry: &str) -> Option<String> {
-    let mut parser = source_parser::SourceParser::new(&h_entry);
+    let mut parser = source_parser::SourceParser::new(h_entry);
 
     parser.goto_end_of(">");
 
@@ -166,32 +382,33 @@ fn extract_clause_no_from_h_entry(h_entry: &str) -> Option<String> {
         parser.goto_end_of("</a>");
     }
 
-    let content_til_end = parser.get_content_til_begin_of("</h");
-    if content_til_end.is_none() {
-        return None;
-    }
-
-    let trimmed_content = content_til_end.unwrap().trim();
+    let trimmed_content = parser.get_content_til_begin_of("</h")?.trim();
 
-    let is_clause_h = trimmed_content.chars().nth(0).unwrap().is_digit(10);
-    if !is_clause_h {
-        return None;
-    }
-
-    let split = trimmed_content.split_whitespace().collect::<Vec<&str>>();
-    if split.len() < 2 {
-        return None;
-    }
+    extract_clause_no_from_heading_text(trimmed_content)
+}
 
-    Some(String::from(split[0]))
+// "4.2.3 Foo" gives "4.2.3", "A.2.1 Foo" gives "A.2.1" and "Annex A (normative): Foo" gives "A"
+fn extract_clause_no_from_heading_text(text: &str) -> Option<String> {
+    static CLAUSE_NO_RE: OnceLock<Regex> = OnceLock::new();
+    let re = CLAUSE_NO_RE.get_or_init(|| {
+        Regex::new(
+            r#"^(?:((?P<clause_no>(\d[\.\da-z]*)|([A-Z](\.\d[\da-z]*)+))\s)|(Annex\s+(?P<annex_no>[A-Z]{1,2})\b))"#,
+        )
+        .unwrap()
+    });
+
+    let cap = re.captures(text)?;
+    cap.name("clause_no")
+        .or_else(|| cap.name("annex_no"))
+        .map(|no| String::from(no.as_str()))
 }
 
-fn remove_hard_spaces(content: &String) -> String {
+fn remove_hard_spaces(content: &str) -> String {
     println!("\tRemoving hard spaces...");
     content.replace("&nbsp;", " ")
 }
 
-fn remove_span_language_en(content: &String) -> String {
+fn remove_span_language_en(content: &str) -> String {
     println!("\tRemoving span_language...");
     let re = Regex::new(r#"(?s:<span lang="en-[A-Z]{2}">(?P<span_content>(.*?))</span>)"#).unwrap();
     String::from(re.replace_all(content, "$span_content"))
@@ -200,16 +417,13 @@ fn remove_span_language_en(content: &String) -> String {
 fn extract_clause_no_from_toc_entry(toc_entry: &str) -> Option<String> {
     let re = Regex::new(r#"(?s:<[.[^<>]]+?>)"#).unwrap();
     let content = re.replace_all(toc_entry, "");
-    let split_content = content.trim().split_whitespace().collect::<Vec<&str>>();
-    if split_content.is_empty() {
-        None
-    } else {
-        if split_content[0].chars().next().unwrap().is_digit(10) {
-            Some(String::from(split_content[0]))
-        } else {
-            None
-        }
-    }
+    extract_clause_no_from_heading_text(content.trim())
+}
+
+// Links of a document without clause ids, "11.222", with the built-in rules
+#[cfg(test)]
+fn clause_links(source: &str) -> String {
+    add_clause_links(source, "11.222", &ClauseIndex::new(), &LinkRules::builtin()).0
 }
 
 #[test]
@@ -217,7 +431,7 @@ fn test_add_clause_links_ts_clause() {
     let source = "Foo TS 11.222 [3] clause 4.55.6 bar";
     let expected =
         r#"Foo <a href="../11.222/11.222.html#4.55.6">TS 11.222 [3] clause 4.55.6</a> bar"#;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
 }
 
 #[test]
@@ -225,7 +439,7 @@ fn test_add_clause_links_ts_with_comma_clause() {
     let source = "Foo TS 11.222 [3], clause 4.55.6 bar";
     let expected =
         r#"Foo <a href="../11.222/11.222.html#4.55.6">TS 11.222 [3], clause 4.55.6</a> bar"#;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
 }
 
 #[test]
@@ -233,7 +447,7 @@ fn test_add_clause_links_ts_clause_with_letter_at_end() {
     let source = "Foo TS 11.222 [3] clause 4.55.6b bar";
     let expected =
         r#"Foo <a href="../11.222/11.222.html#4.55.6b">TS 11.222 [3] clause 4.55.6b</a> bar"#;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
 }
 
 #[test]
@@ -241,14 +455,14 @@ fn test_add_clause_links_ts_clause_with_dot_at_end() {
     let source = "Foo TS 11.222 [3] clause 4.55.6. Bar";
     let expected =
         r#"Foo <a href="../11.222/11.222.html#4.55.6">TS 11.222 [3] clause 4.55.6</a>. Bar"#;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
 }
 
 #[test]
 fn test_add_clause_links_ts_without_ts_word_clause() {
     let source = "Foo 11.222 [3] clause 4.55.6 bar";
     let expected = r#"Foo <a href="../11.222/11.222.html#4.55.6">11.222 [3] clause 4.55.6</a> bar"#;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
 }
 
 #[test]
@@ -256,70 +470,179 @@ fn test_add_clause_links_clause_of_ts() {
     let source = "Foo in clause 11.2.33 of TS 44.555 [6] bar";
     let expected =
         r#"Foo <a href="../44.555/44.555.html#11.2.33">in clause 11.2.33 of TS 44.555 [6]</a> bar"#;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
 }
 
 #[test]
 fn test_add_clause_links_clause_in_ts() {
     let source = "Foo TS 11.222 [33] bar";
     let expected = r#"Foo <a href="../11.222/11.222.html">TS 11.222 [33]</a> bar"#;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
 }
 
 #[test]
 fn test_add_clause_links_clause_some_text_in_ts() {
     let source = "Foo clause 11.2.33 (Some text) in TS 44.555 [6] bar";
     let expected = r#"Foo <a href="../44.555/44.555.html#11.2.33">clause 11.2.33 (Some text) in TS 44.555 [6]</a> bar"#;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
 }
 
 #[test]
 fn test_add_clause_links_clause() {
     let source = "Foo clause 11.2.33 bar";
     let expected = r##"Foo <a href="#11.2.33">clause 11.2.33</a> bar"##;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
 }
 
 #[test]
 fn test_add_clause_links_clause_capital() {
     let source = "Foo Clause 11.2.33, bar";
     let expected = r##"Foo <a href="#11.2.33">Clause 11.2.33</a>, bar"##;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
 }
 
 #[test]
 fn test_add_clause_links_in_clause_no() {
     let source = "Foo in 11.2.33 bar";
     let expected = r##"Foo <a href="#11.2.33">in 11.2.33</a> bar"##;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
 }
 
 #[test]
 fn test_add_clause_links_see_clause_no() {
     let source = "Foo see 11.2.33 bar";
     let expected = r##"Foo <a href="#11.2.33">see 11.2.33</a> bar"##;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
 }
 
 #[test]
 fn test_add_clause_links_ts() {
     let source = "Foo see 11.2.33 bar";
     let expected = r##"Foo <a href="#11.2.33">see 11.2.33</a> bar"##;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
 }
 
 #[test]
 fn test_add_clause_links_doesnt_replace_regular_sentence_with_in_see() {
     let source = "Foo in bar, see baz. Qux";
     let expected = r#"Foo in bar, see baz. Qux"#;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
 }
 
 #[test]
 fn test_add_clause_links_doesnt_replace_standalone_number() {
     let source = "Foo 4.5 bar";
     let expected = r#"Foo 4.5 bar"#;
-    assert_eq!(add_clause_links(&source), expected)
+    assert_eq!(clause_links(source), expected)
+}
+
+#[test]
+fn test_add_clause_links_clause_of_ts_without_reference_no() {
+    let source = "Foo clause 11.2.33 of TS 44.555 bar";
+    let expected =
+        r#"Foo <a href="../44.555/44.555.html#11.2.33">clause 11.2.33 of TS 44.555</a> bar"#;
+    assert_eq!(clause_links(source), expected)
+}
+
+#[test]
+fn test_add_clause_links_clauses_range() {
+    let source = "Foo clauses 4.2.2 to 4.2.5 bar";
+    let expected = r##"Foo clauses <a href="#4.2.2">4.2.2</a> to <a href="#4.2.5">4.2.5</a> bar"##;
+    assert_eq!(clause_links(source), expected)
+}
+
+#[test]
+fn test_add_clause_links_clauses_enumeration() {
+    let source = "Foo see clauses 5.3.2, 5.3.3 and 5.3.4. Bar";
+    let expected = r##"Foo see clauses <a href="#5.3.2">5.3.2</a>, <a href="#5.3.3">5.3.3</a> and <a href="#5.3.4">5.3.4</a>. Bar"##;
+    assert_eq!(clause_links(source), expected)
+}
+
+#[test]
+fn test_add_clause_links_clauses_range_and_enumeration() {
+    let source = "Foo clauses 4.2.2 to 4.2.5 and 4.3 bar";
+    let expected = r##"Foo clauses <a href="#4.2.2">4.2.2</a> to <a href="#4.2.5">4.2.5</a> and <a href="#4.3">4.3</a> bar"##;
+    assert_eq!(clause_links(source), expected)
+}
+
+#[test]
+fn test_add_clause_links_doesnt_list_numbers_after_clause() {
+    let source = "Foo clause 5.3.2, 16 bits and clause 5.3.3 or 2 octets";
+    let expected = r##"Foo <a href="#5.3.2">clause 5.3.2</a>, 16 bits and <a href="#5.3.3">clause 5.3.3</a> or 2 octets"##;
+    assert_eq!(clause_links(source), expected)
+}
+
+#[test]
+fn test_add_clause_links_clause_list_of_ts() {
+    let source = "Foo clause 4.3.2.2.1 and 4.3.2.2.2 of TS 23.502 bar";
+    let expected = r#"Foo clause <a href="../23.502/23.502.html#4.3.2.2.1">4.3.2.2.1</a> and <a href="../23.502/23.502.html#4.3.2.2.2">4.3.2.2.2</a> of <a href="../23.502/23.502.html">TS 23.502</a> bar"#;
+    assert_eq!(clause_links(source), expected)
+}
+
+#[test]
+fn test_add_clause_links_ts_clause_list() {
+    let source = "Foo TS 11.222 [3], clauses 4.5 or 4.6 bar";
+    let expected = r#"Foo <a href="../11.222/11.222.html">TS 11.222 [3]</a>, clauses <a href="../11.222/11.222.html#4.5">4.5</a> or <a href="../11.222/11.222.html#4.6">4.6</a> bar"#;
+    assert_eq!(clause_links(source), expected)
+}
+
+#[test]
+fn test_add_clause_links_annex() {
+    let source = "Foo see Annex B. Bar";
+    let expected = r##"Foo <a href="#B">see Annex B</a>. Bar"##;
+    assert_eq!(clause_links(source), expected)
+}
+
+#[test]
+fn test_add_clause_links_annex_of_ts() {
+    let source = "Foo annex C of TS 44.555 [6] bar";
+    let expected = r#"Foo <a href="../44.555/44.555.html#C">annex C of TS 44.555 [6]</a> bar"#;
+    assert_eq!(clause_links(source), expected)
+}
+
+#[test]
+fn test_add_clause_links_ts_annex() {
+    let source = "Foo TS 11.222 [3], Annex A bar";
+    let expected = r#"Foo <a href="../11.222/11.222.html#A">TS 11.222 [3], Annex A</a> bar"#;
+    assert_eq!(clause_links(source), expected)
+}
+
+#[test]
+fn test_add_clause_links_annex_clause() {
+    let source = "Foo clause D.3.2 of TS 44.555 [6] and clauses A.1 to A.3 bar";
+    let expected = r##"Foo <a href="../44.555/44.555.html#D.3.2">clause D.3.2 of TS 44.555 [6]</a> and clauses <a href="#A.1">A.1</a> to <a href="#A.3">A.3</a> bar"##;
+    assert_eq!(clause_links(source), expected)
+}
+
+#[test]
+fn test_add_clause_links_doesnt_replace_annex_word() {
+    let source = "Foo the Annexes and Annex Bar";
+    let expected = r#"Foo the Annexes and Annex Bar"#;
+    assert_eq!(clause_links(source), expected)
+}
+
+#[test]
+fn test_add_clause_links_skips_anchors_headings_and_attributes() {
+    let source = r##"<a href="#4.2"><p>4.2 Foo in 5.3</p></a><h2 id="4.3">4.3 Bar in 4.2</h2><p style="margin: 0.5in 1.5in">see 4.2</p>"##;
+    let expected = r##"<a href="#4.2"><p>4.2 Foo in 5.3</p></a><h2 id="4.3">4.3 Bar in 4.2</h2><p style="margin: 0.5in 1.5in"><a href="#4.2">see 4.2</a></p>"##;
+    assert_eq!(clause_links(source), expected)
+}
+
+#[test]
+fn test_add_clause_links_skips_clauses_missing_in_document() {
+    let mut clause_index = ClauseIndex::new();
+    clause_index.insert(
+        "11.222",
+        ["4.2", "4.3"].iter().map(|c| c.to_string()).collect(),
+    );
+    clause_index.insert("44.555", ["5.1"].iter().map(|c| c.to_string()).collect());
+
+    let source = "Foo in 3.5 seconds, see 4.2, clauses 4.3 and 5.0 dB, clause 5.1 of TS 44.555 [6], clause 4.2 of TS 44.555 [6] bar";
+    let expected = r##"Foo in 3.5 seconds, <a href="#4.2">see 4.2</a>, clauses <a href="#4.3">4.3</a> and 5.0 dB, <a href="../44.555/44.555.html#5.1">clause 5.1 of TS 44.555 [6]</a>, <a href="../44.555/44.555.html">clause 4.2 of TS 44.555 [6]</a> bar"##;
+    assert_eq!(
+        add_clause_links(source, "11.222", &clause_index, &LinkRules::builtin()).0,
+        expected
+    )
 }
 
 #[test]
@@ -357,6 +680,61 @@ Lorem ipsum dolor sit amet, consectetur adipiscing elit, .</p>"##;
     assert_eq!(result.unwrap(), expected)
 }
 
+#[test]
+fn test_extract_clause_no_from_annex_toc_entry() {
+    let source = r#"<p lang="en-GB" style="margin-left: 0.79in;">
+	Annex A (normative): Foo	<a href="\#__RefHeading___Toc19177586">164</a></p>"#;
+
+    let result = extract_clause_no_from_toc_entry(source);
+    assert_eq!(result.unwrap(), "A")
+}
+
+#[test]
+fn test_extract_clause_no_from_annex_h_entry() {
+    let source = r##"<h8 lang="en-US" class="western"><a name="__RefHeading___Toc19183553"></a>
+Annex B (informative):<br/>Lorem ipsum dolor sit amet</h8>"##;
+
+    let result = extract_clause_no_from_h_entry(source);
+    assert_eq!(result.unwrap(), "B")
+}
+
+#[test]
+fn test_extract_clause_no_from_heading_mentioning_annex() {
+    assert_eq!(
+        extract_clause_no_from_heading_text("5.1\tUse of Annex B procedures"),
+        Some(String::from("5.1"))
+    );
+    assert_eq!(
+        extract_clause_no_from_heading_text("Procedures of Annex B"),
+        None
+    );
+}
+
+#[test]
+fn test_add_clause_ids_skips_head_and_hr() {
+    let source = "<head><title>Annex B</title></head><hr/><p>x</p><h2>Handling of Annex B</h2><h2>4.5 Foo</h2>";
+    let expected =
+        "<head><title>Annex B</title></head><hr/><p>x</p><h2>Handling of Annex B</h2><h2 id=\"4.5\" >4.5 Foo</h2>";
+
+    assert_eq!(add_clause_ids(source), expected);
+}
+
+#[test]
+fn test_extract_clause_no_from_annex_subclause_h_entry() {
+    let source = r##"<h2 lang="en-US" class="western"><a name="__RefHeading___Toc19183553"></a>
+B.2.1	Lorem ipsum dolor sit amet</h2>"##;
+
+    let result = extract_clause_no_from_h_entry(source);
+    assert_eq!(result.unwrap(), "B.2.1")
+}
+
+#[test]
+fn test_extract_clause_no_from_h_entry_without_clause_no() {
+    let source = r##"<h1 lang="en-US" class="western">Foreword</h1>"##;
+
+    assert!(extract_clause_no_from_h_entry(source).is_none())
+}
+
 #[test]
 fn test_add_clauses_ids() {
     let source = r##"<h1 lang="en-US" class="western"><a name="__RefHeading___Toc19183553"></a>
@@ -382,6 +760,17 @@ Lorem ipsum dolor sit amet, consectetur adipiscing elit, .</p>"##;
     assert_eq!(add_clause_ids(&source), expected);
 }
 
+#[test]
+fn test_collect_clause_ids() {
+    let source = r##"<h1 lang="en-US" class="western">1.2.3	Lorem ipsum</h1>
+<p lang="en-GB" class="western">Lorem ipsum dolor sit amet.</p>
+<h2 lang="en-US" class="western"><span lang="en-GB">A.4</span>&nbsp;Lorem ipsum</h2>
+<h2 lang="en-US" class="western">Foreword</h2>"##;
+
+    let expected = ["1.2.3", "A.4"].iter().map(|c| c.to_string()).collect();
+    assert_eq!(collect_clause_ids(source), expected);
+}
+
 #[test]
 fn test_add_figure_ids() {
     let source = "<b>Figure 4.13.5.7b-1: Location";
@@ -394,5 +783,107 @@ fn test_add_figure_links() {
     let source = "Foo Figure 1.2.3-4 shows support. <b>Figure 5.6-7: Location";
     let expected =
         r##"Foo <a href="#1.2.3-4">Figure 1.2.3-4</a> shows support. <b>Figure 5.6-7: Location"##;
-    assert_eq!(add_figure_links(&source), expected);
+    assert_eq!(
+        add_figure_links(source, "11.222", &LinkRules::builtin()),
+        expected
+    );
+}
+
+#[test]
+fn test_add_figure_links_at_end_of_text() {
+    let source = "<p>See Figure 1.2.3-4</p><p>Figure 1.2.3-5, foo</p>";
+    let expected = r##"<p>See <a href="#1.2.3-4">Figure 1.2.3-4</a></p><p><a href="#1.2.3-5">Figure 1.2.3-5</a>, foo</p>"##;
+    assert_eq!(
+        add_figure_links(source, "11.222", &LinkRules::builtin()),
+        expected
+    );
+}
+
+#[test]
+fn test_add_table_ids() {
+    let source = "<b>Table 4.13.5.7b-1: Location";
+    let expected = r#"<b id="table-4.13.5.7b-1">Table 4.13.5.7b-1: Location"#;
+    assert_eq!(add_table_ids(source), expected);
+}
+
+#[test]
+fn test_add_figure_links_tables() {
+    let source = "Foo table 1.2.3-4 shows support. <b>Table 5.6-7: Location";
+    let expected = r##"Foo <a href="#table-1.2.3-4">table 1.2.3-4</a> shows support. <b>Table 5.6-7: Location"##;
+    assert_eq!(
+        add_figure_links(source, "11.222", &LinkRules::builtin()),
+        expected
+    );
+}
+
+#[test]
+fn test_add_clause_links_returns_references() {
+    let source = r##"Foo see 4.2.<h2 id="5.1">5.1 Bar</h2>Baz clauses 4.3 and 4.4 of TS 44.555 [6], TS 33.444 [7]"##;
+    let (_, references) =
+        add_clause_links(source, "11.222", &ClauseIndex::new(), &LinkRules::builtin());
+
+    let reference =
+        |source_clause: Option<&str>, target_ts_no: &str, target_clause: Option<&str>| {
+            ClauseReference {
+                source_clause: source_clause.map(String::from),
+                target_ts_no: String::from(target_ts_no),
+                target_clause: target_clause.map(String::from),
+            }
+        };
+    let expected = vec![
+        reference(None, "11.222", Some("4.2")),
+        reference(Some("5.1"), "44.555", Some("4.3")),
+        reference(Some("5.1"), "44.555", Some("4.4")),
+        reference(Some("5.1"), "44.555", None),
+        reference(Some("5.1"), "33.444", None),
+    ];
+    assert_eq!(references, expected);
+}
+
+#[test]
+fn test_better_toc_links_libreoffice_toc() {
+    let source = r##"<div id="Table of Contents1" dir="ltr"><p class="toc1">4	Foo	7</p><p>Bar</p></div><p>4 Baz</p>"##;
+    let expected = r##"<div id="Table of Contents1" dir="ltr"><a href="#4"><p class="toc1">4	Foo	7</p></a><p>Bar</p></div><p>4 Baz</p>"##;
+
+    assert_eq!(better_toc(source), expected);
+}
+
+#[test]
+fn test_better_toc_generates_toc_without_libreoffice_toc() {
+    let source = r##"<p>Foreword</p>
+<h1 id="4"  class="western">4	Foo</h1>
+<h2 id="4.1"  class="western">4.1	Bar</h2>"##;
+    let expected = r##"<p>Foreword</p>
+<div id="toc" class="generated-toc"><p class="toc-title">Contents</p><ul><li><a href="#4">4 Foo</a><ul><li><a href="#4.1">4.1 Bar</a></li></ul></li></ul></div>
+<h1 id="4"  class="western">4	Foo</h1>
+<h2 id="4.1"  class="western">4.1	Bar</h2>"##;
+
+    assert_eq!(better_toc(source), expected);
+}
+
+#[test]
+fn test_better_toc_without_headings() {
+    let source = "<p>Foo</p>";
+    assert_eq!(better_toc(source), source);
+}
+
+#[test]
+fn test_add_reference_controls() {
+    let metadata = SpecMetadata {
+        ts_no: String::from("23.502"),
+        version: String::from("17.4.0"),
+        ..SpecMetadata::default()
+    };
+    let source = r#"<h5 id="4.3.2.2.1"  class="western">4.3.2.2.1	Foo</h5>
+<h1 id="A"  class="western">Annex A (normative): Bar</h1>
+<p><b id="table-4.2-1">Table 4.2-1: Baz</b></p>
+<p><b id="4.2-1">Figure 4.2-1: Qux</b></p>
+<h2 class="western">Foreword</h2>"#;
+    let expected = r##"<h5 id="4.3.2.2.1"  class="western">4.3.2.2.1	Foo<span class="reference-controls"><a class="permalink" href="#4.3.2.2.1" title="Permalink"></a><button class="copy-reference" type="button" title="Copy reference" data-reference="TS 23.502 v17.4.0 clause 4.3.2.2.1" data-target="4.3.2.2.1"></button></span></h5>
+<h1 id="A"  class="western">Annex A (normative): Bar<span class="reference-controls"><a class="permalink" href="#A" title="Permalink"></a><button class="copy-reference" type="button" title="Copy reference" data-reference="TS 23.502 v17.4.0 Annex A" data-target="A"></button></span></h1>
+<p><b id="table-4.2-1">Table 4.2-1: Baz</b><span class="reference-controls"><a class="permalink" href="#table-4.2-1" title="Permalink"></a><button class="copy-reference" type="button" title="Copy reference" data-reference="TS 23.502 v17.4.0 Table 4.2-1" data-target="table-4.2-1"></button></span></p>
+<p><b id="4.2-1">Figure 4.2-1: Qux</b><span class="reference-controls"><a class="permalink" href="#4.2-1" title="Permalink"></a><button class="copy-reference" type="button" title="Copy reference" data-reference="TS 23.502 v17.4.0 Figure 4.2-1" data-target="4.2-1"></button></span></p>
+<h2 class="western">Foreword</h2>"##;
+
+    assert_eq!(add_reference_controls(source, &metadata), expected);
 }
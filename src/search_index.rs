@@ -0,0 +1,409 @@
+use crate::html_text;
+use crate::html_tokens;
+
+use failure::ResultExt;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+pub const SEARCH_INDEX_FILE_NAME: &str = "search_index.json";
+const SEARCH_INDEX_SCRIPT_FILE_NAME: &str = "search_index.js";
+const SEARCH_PAGE_FILE_NAME: &str = "search.html";
+
+// Text of one clause, from its heading to the next heading. Paragraphs are separated by new lines.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ClauseEntry {
+    pub ts_no: String,
+    pub clause: String,
+    pub heading: String,
+    pub text: String,
+}
+
+// Writes the clause index of all documents in the output directory, together with a search page.
+// The page loads the index from a script file, so it works when opened from the disk.
+pub fn generate_search_index(out_path: &std::path::Path) -> Result<(), failure::Error> {
+    println!("Generating search index...");
+
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(out_path)
+        .with_context(|_| format!("could not read output directory `{}`", out_path.display()))?
+    {
+        let dir = entry?.path();
+        let ts_no = dir.file_name().unwrap().to_string_lossy().to_string();
+        let html_path = dir.join(format!("{}.html", ts_no));
+        if !html_path.is_file() {
+            continue;
+        }
+
+        println!("\t{}", html_path.display());
+        let content = std::fs::read_to_string(&html_path)
+            .with_context(|_| format!("could not read HTML file `{}`", html_path.display()))?;
+        entries.extend(extract_clauses(&content, &ts_no));
+    }
+    entries.sort_by(|a, b| a.ts_no.cmp(&b.ts_no));
+
+    let index = serde_json::to_string(&entries)?;
+    let outputs = [
+        (SEARCH_INDEX_FILE_NAME, index.clone()),
+        (
+            SEARCH_INDEX_SCRIPT_FILE_NAME,
+            format!("var SEARCH_INDEX = {};\n", index),
+        ),
+        (SEARCH_PAGE_FILE_NAME, String::from(SEARCH_PAGE)),
+    ];
+    for (file_name, content) in outputs.iter() {
+        let path = out_path.join(file_name);
+        std::fs::write(&path, content)
+            .with_context(|_| format!("could not write search file `{}`", path.display()))?;
+    }
+
+    Ok(())
+}
+
+pub fn load_search_index(out_path: &std::path::Path) -> Result<Vec<ClauseEntry>, failure::Error> {
+    let path = out_path.join(SEARCH_INDEX_FILE_NAME);
+    let content = std::fs::read_to_string(&path)
+        .with_context(|_| format!("could not read search index `{}`", path.display()))?;
+    let entries = serde_json::from_str(&content)
+        .with_context(|_| format!("could not parse search index `{}`", path.display()))?;
+
+    Ok(entries)
+}
+
+// Clauses containing every word of the query, best first. Ranked the same way as on the search
+// page: words in the heading count the most, the whole query in the heading even more.
+pub fn search<'a>(entries: &'a [ClauseEntry], query: &str) -> Vec<&'a ClauseEntry> {
+    let query = query.trim().to_lowercase();
+    let terms = query.split_whitespace().collect::<Vec<_>>();
+    if terms.is_empty() {
+        return Vec::new();
+    }
+
+    let mut hits = entries
+        .iter()
+        .filter_map(|entry| {
+            let score = score(entry, &query, &terms);
+            if score > 0 {
+                Some((score, entry))
+            } else {
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+    hits.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+
+    hits.into_iter().map(|(_, entry)| entry).collect()
+}
+
+fn score(entry: &ClauseEntry, query: &str, terms: &[&str]) -> usize {
+    let heading = entry.heading.to_lowercase();
+    let text = entry.text.to_lowercase();
+
+    let mut result = 0;
+    for term in terms {
+        let in_heading = heading.contains(term);
+        if !in_heading && !text.contains(term) {
+            return 0;
+        }
+        result += if in_heading { 10 } else { 1 };
+    }
+
+    if heading.contains(query) {
+        result += 50;
+    } else if text.contains(query) {
+        result += 5;
+    }
+    result
+}
+
+pub fn find_clause<'a>(
+    entries: &'a [ClauseEntry],
+    ts_no: &str,
+    clause: &str,
+) -> Option<&'a ClauseEntry> {
+    entries
+        .iter()
+        .find(|entry| entry.ts_no == ts_no && entry.clause == clause)
+}
+
+// One line per hit: "23.502 4.3.2.2.1 UE Requested PDU Session Establishment", or a Markdown list
+// item linking the clause in the output directory
+pub fn format_hit(entry: &ClauseEntry, markdown: bool) -> String {
+    if markdown {
+        format!(
+            "- [TS {0} clause {1}]({0}/{0}.html#{1}) {2}",
+            entry.ts_no, entry.clause, entry.heading
+        )
+    } else {
+        format!("{} {} {}", entry.ts_no, entry.clause, entry.heading)
+    }
+}
+
+pub fn format_clause(entry: &ClauseEntry, markdown: bool) -> String {
+    let title = format!(
+        "TS {} clause {} {}",
+        entry.ts_no, entry.clause, entry.heading
+    );
+    let paragraphs = entry.text.lines().collect::<Vec<_>>().join("\n\n");
+
+    if markdown {
+        format!("**{}**\n\n{}\n", title.trim(), paragraphs)
+    } else {
+        format!("{}\n\n{}\n", title.trim(), paragraphs)
+    }
+}
+
+fn extract_clauses(content: &str, ts_no: &str) -> Vec<ClauseEntry> {
+    let heading_re = Regex::new(html_tokens::HEADING_WITH_ID_PATTERN).unwrap();
+    let backlinks_re = Regex::new(r#"(?s)<details class="backlinks">.*?</details>"#).unwrap();
+    // The template puts the document in <main> and its scripts after it
+    let body_end_re = Regex::new(r#"(?i)</main>|</body>"#).unwrap();
+    let script_re = Regex::new(r#"(?is)<(script|style)[\s>].*?</(script|style)>"#).unwrap();
+
+    let headings = heading_re.captures_iter(content).collect::<Vec<_>>();
+    let mut entries = Vec::new();
+
+    for (i, cap) in headings.iter().enumerate() {
+        let clause = &cap["id"];
+        let text_begin = cap.get(0).unwrap().end();
+        let text_end = match headings.get(i + 1) {
+            Some(next) => next.get(0).unwrap().start(),
+            None => body_end_re
+                .find_at(content, text_begin)
+                .map(|m| m.start())
+                .unwrap_or_else(|| content.len()),
+        };
+
+        let heading = html_text::to_plain_text(&cap["heading"]);
+        let heading = heading
+            .strip_prefix(&format!("Annex {}", clause))
+            .or_else(|| heading.strip_prefix(clause))
+            .unwrap_or(&heading)
+            .trim();
+        let text = backlinks_re.replace_all(&content[text_begin..text_end], "");
+        let text = script_re.replace_all(&text, "");
+
+        entries.push(ClauseEntry {
+            ts_no: String::from(ts_no),
+            clause: String::from(clause),
+            heading: String::from(heading),
+            text: to_paragraphs(&text),
+        });
+    }
+
+    entries
+}
+
+// Plain text with a line for every paragraph, list item and table row
+fn to_paragraphs(html: &str) -> String {
+    let block_end_re = Regex::new(r#"(?i)</(p|li|tr|pre|h\d)>|<br\s*/?>"#).unwrap();
+
+    block_end_re
+        .split(html)
+        .map(html_text::to_plain_text)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+const SEARCH_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Search 3GPP specifications</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+input { font-size: 1.2em; width: 100%; padding: 0.3em; box-sizing: border-box; }
+li { margin: 0.8em 0; }
+.spec { color: #666; }
+.snippet { color: #333; font-size: 0.9em; }
+mark { background: #ff8; }
+</style>
+<script src="search_index.js"></script>
+</head>
+<body>
+<h1>Search 3GPP specifications</h1>
+<p><a href="index.html">All specifications</a></p>
+<input id="query" type="search" placeholder="PDU Session Establishment" autofocus>
+<p id="summary"></p>
+<ol id="results"></ol>
+<script>
+var MAX_RESULTS = 100;
+
+function escapeHtml(s) {
+  return s.replace(/&/g, "&amp;").replace(/</g, "&lt;").replace(/>/g, "&gt;").replace(/"/g, "&quot;");
+}
+
+// One pass over the raw text, so a term never matches the markup added for another one
+function highlight(text, terms) {
+  var alternatives = terms.filter(function (term) { return term.length > 0; })
+    .sort(function (a, b) { return b.length - a.length; })
+    .map(function (term) { return term.replace(/[.*+?^${}()|[\]\\]/g, "\\$&"); });
+  if (alternatives.length === 0) {
+    return escapeHtml(text);
+  }
+  // With a capturing group, the matches are the odd pieces of the split
+  return text.split(new RegExp("(" + alternatives.join("|") + ")", "i"))
+    .map(function (piece, i) {
+      return i % 2 ? "<mark>" + escapeHtml(piece) + "</mark>" : escapeHtml(piece);
+    })
+    .join("");
+}
+
+function snippet(text, terms) {
+  var lower = text.toLowerCase();
+  var pos = lower.indexOf(terms[0]);
+  var begin = Math.max(0, pos - 80);
+  var result = text.substring(begin, begin + 240).replace(/\n/g, " ");
+  return (begin > 0 ? "…" : "") + result + "…";
+}
+
+// Every term has to appear in the heading or the text, hits in the heading count the most
+function score(entry, query, terms) {
+  var heading = entry.heading.toLowerCase();
+  var text = entry.text.toLowerCase();
+  var result = 0;
+  for (var i = 0; i < terms.length; i++) {
+    var inHeading = heading.indexOf(terms[i]) >= 0;
+    if (!inHeading && text.indexOf(terms[i]) < 0) {
+      return 0;
+    }
+    result += inHeading ? 10 : 1;
+  }
+  if (heading.indexOf(query) >= 0) {
+    result += 50;
+  } else if (text.indexOf(query) >= 0) {
+    result += 5;
+  }
+  return result;
+}
+
+function search() {
+  var query = document.getElementById("query").value.trim().toLowerCase();
+  var terms = query.split(/\s+/).filter(function (t) { return t.length > 0; });
+  var results = document.getElementById("results");
+  var summary = document.getElementById("summary");
+  results.innerHTML = "";
+  summary.textContent = "";
+  if (terms.length === 0) {
+    return;
+  }
+
+  var hits = [];
+  SEARCH_INDEX.forEach(function (entry) {
+    var s = score(entry, query, terms);
+    if (s > 0) {
+      hits.push({ entry: entry, score: s });
+    }
+  });
+  hits.sort(function (a, b) { return b.score - a.score; });
+  summary.textContent = hits.length + " matching clause(s)";
+
+  hits.slice(0, MAX_RESULTS).forEach(function (hit) {
+    var e = hit.entry;
+    var item = document.createElement("li");
+    item.innerHTML = '<a href="' + e.ts_no + "/" + e.ts_no + ".html#" + encodeURIComponent(e.clause) + '">'
+      + highlight(e.clause + " " + e.heading, terms) + "</a> "
+      + '<span class="spec">TS ' + e.ts_no + " clause " + escapeHtml(e.clause) + "</span>"
+      + '<div class="snippet">' + highlight(snippet(e.text, terms), terms) + "</div>";
+    results.appendChild(item);
+  });
+}
+
+document.getElementById("query").addEventListener("input", search);
+</script>
+</body>
+</html>
+"#;
+
+#[test]
+fn test_extract_clauses() {
+    let source = r#"<body><p>Cover</p>
+<h1 id="4"  class="western">4	General</h1>
+<p>Lorem <b>ipsum</b></p><p>dolor</p>
+<h2 id="4.1"  class="western">4.1	Foo</h2><details class="backlinks"><summary>Referenced from 1 place</summary></details>
+<table><tr><td>a</td><td>b</td></tr></table>
+<h1 id="A"  class="western">Annex A (normative):<br/>Bar</h1>
+<p>Baz</p>
+</body>"#;
+
+    let entry = |clause: &str, heading: &str, text: &str| ClauseEntry {
+        ts_no: String::from("23.501"),
+        clause: String::from(clause),
+        heading: String::from(heading),
+        text: String::from(text),
+    };
+    let expected = vec![
+        entry("4", "General", "Lorem ipsum\ndolor"),
+        entry("4.1", "Foo", "a b"),
+        entry("A", "(normative): Bar", "Baz"),
+    ];
+
+    assert_eq!(extract_clauses(source, "23.501"), expected);
+}
+
+#[test]
+fn test_extract_clauses_stops_at_end_of_document() {
+    use crate::spec_metadata::SpecMetadata;
+    use crate::template::Template;
+
+    let source = r#"<html><body><h1 id="4"  class="western">4	General</h1>
+<p>Lorem</p><script>var x = "ipsum";</script>
+<p>dolor</p></body></html>"#;
+    let page = Template::builtin().render_document(source, &SpecMetadata::default(), true);
+
+    let entries = extract_clauses(&page, "23.501");
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].text, "Lorem\ndolor");
+}
+
+#[cfg(test)]
+fn test_entries() -> Vec<ClauseEntry> {
+    let entry = |clause: &str, heading: &str, text: &str| ClauseEntry {
+        ts_no: String::from("23.502"),
+        clause: String::from(clause),
+        heading: String::from(heading),
+        text: String::from(text),
+    };
+
+    vec![
+        entry(
+            "4.2",
+            "Registration",
+            "The PDU Session is established later.",
+        ),
+        entry("4.3.2", "PDU Session establishment", "Lorem ipsum\ndolor"),
+        entry("4.3.3", "PDU Session modification", "Lorem ipsum"),
+    ]
+}
+
+#[test]
+fn test_search_ranks_heading_matches_first() {
+    let entries = test_entries();
+    let hits = search(&entries, "pdu session establish")
+        .iter()
+        .map(|entry| entry.clause.as_str())
+        .collect::<Vec<_>>();
+
+    assert_eq!(hits, vec!["4.3.2", "4.2"]);
+    assert!(search(&entries, "  ").is_empty());
+}
+
+#[test]
+fn test_format_clause() {
+    let entries = test_entries();
+    let entry = find_clause(&entries, "23.502", "4.3.2").unwrap();
+
+    assert_eq!(
+        format_clause(entry, false),
+        "TS 23.502 clause 4.3.2 PDU Session establishment\n\nLorem ipsum\n\ndolor\n"
+    );
+    assert_eq!(
+        format_clause(entry, true),
+        "**TS 23.502 clause 4.3.2 PDU Session establishment**\n\nLorem ipsum\n\ndolor\n"
+    );
+    assert_eq!(
+        format_hit(entry, true),
+        "- [TS 23.502 clause 4.3.2](23.502/23.502.html#4.3.2) PDU Session establishment"
+    );
+    assert!(find_clause(&entries, "23.501", "4.3.2").is_none());
+}
@@ -0,0 +1,165 @@
+use crate::html_text;
+use crate::spec_metadata;
+use crate::spec_metadata::SpecMetadata;
+
+use failure::ResultExt;
+use std::collections::BTreeMap;
+
+struct IndexEntry {
+    ts_no: String,
+    metadata: SpecMetadata,
+}
+
+const SERIES_NAMES: [(&str, &str); 18] = [
+    ("21", "Requirements"),
+    ("22", "Service aspects (stage 1)"),
+    ("23", "Technical realization (stage 2)"),
+    (
+        "24",
+        "Signalling protocols (stage 3) - user equipment to network",
+    ),
+    ("25", "Radio aspects"),
+    ("26", "CODECs"),
+    ("27", "Data"),
+    ("28", "Management and orchestration"),
+    ("29", "Signalling protocols (stage 3) - core network"),
+    ("31", "Subscriber Identity Module (SIM / USIM), IC Cards"),
+    ("32", "OAM&P and Charging"),
+    ("33", "Security aspects"),
+    ("34", "UE and (U)SIM test specifications"),
+    ("35", "Security algorithms"),
+    ("36", "LTE (Evolved UTRA) radio technology"),
+    ("37", "Multiple radio access technology aspects"),
+    ("38", "Radio technology beyond LTE"),
+    ("45", "GERAN radio aspects"),
+];
+
+// Writes index.html listing every spec found in the output directory, grouped by series
+pub fn generate_site_index(out_path: &std::path::Path) -> Result<(), failure::Error> {
+    println!("Generating site index...");
+
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(out_path)
+        .with_context(|_| format!("could not read output directory `{}`", out_path.display()))?
+    {
+        let dir = entry?.path();
+        let ts_no = dir.file_name().unwrap().to_string_lossy().to_string();
+        let html_path = dir.join(format!("{}.html", ts_no));
+        if !html_path.is_file() {
+            continue;
+        }
+
+        // Documents converted before metadata.json existed still have their cover page
+        let metadata = match spec_metadata::load_metadata(&dir)? {
+            Some(metadata) => metadata,
+            None => {
+                let content = std::fs::read_to_string(&html_path).with_context(|_| {
+                    format!("could not read HTML file `{}`", html_path.display())
+                })?;
+                spec_metadata::extract_metadata(&content, &ts_no)
+            }
+        };
+        entries.push(IndexEntry { ts_no, metadata });
+    }
+    entries.sort_by(|a, b| a.ts_no.cmp(&b.ts_no));
+
+    let index_path = out_path.join("index.html");
+    std::fs::write(&index_path, render_site_index(&entries))
+        .with_context(|_| format!("could not write site index `{}`", index_path.display()))?;
+
+    Ok(())
+}
+
+fn series_name(series: &str) -> &str {
+    SERIES_NAMES
+        .iter()
+        .find(|(no, _)| *no == series)
+        .map(|(_, name)| *name)
+        .unwrap_or("")
+}
+
+fn render_site_index(entries: &[IndexEntry]) -> String {
+    let mut series: BTreeMap<&str, Vec<&IndexEntry>> = BTreeMap::new();
+    for entry in entries {
+        let series_no = entry.ts_no.split('.').next().unwrap();
+        series.entry(series_no).or_default().push(entry);
+    }
+
+    let mut body = String::new();
+    for (series_no, entries) in &series {
+        body.push_str(&format!(
+            "<h2 id=\"series-{0}\">{0} series <small>{1}</small></h2>\n<table>\n<tr><th>TS</th><th>Title</th><th>Version</th><th>Release</th></tr>\n",
+            series_no,
+            html_text::escape(series_name(series_no))
+        ));
+
+        for entry in entries {
+            let or_dash = |s: &str| {
+                if s.is_empty() {
+                    String::from("-")
+                } else {
+                    html_text::escape(s)
+                }
+            };
+            body.push_str(&format!(
+                "<tr><td><a href=\"{0}/{0}.html\">TS {0}</a></td><td>{1}</td><td>{2}</td><td>{3}</td></tr>\n",
+                entry.ts_no,
+                or_dash(&entry.metadata.title),
+                or_dash(&entry.metadata.version),
+                or_dash(&entry.metadata.release)
+            ));
+        }
+
+        body.push_str("</table>\n");
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>3GPP specifications</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+table {{ border-collapse: collapse; width: 100%; }}
+th, td {{ border-bottom: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }}
+small {{ color: #666; font-weight: normal; }}
+</style>
+</head>
+<body>
+<h1>3GPP specifications</h1>
+<p><a href="search.html">Search all specifications</a> · <a href="glossary.html">Glossary</a></p>
+{}</body>
+</html>
+"#,
+        body
+    )
+}
+
+#[test]
+fn test_render_site_index_groups_by_series() {
+    let entry = |ts_no: &str, title: &str| IndexEntry {
+        ts_no: String::from(ts_no),
+        metadata: SpecMetadata {
+            ts_no: String::from(ts_no),
+            title: String::from(title),
+            version: String::from("16.4.0"),
+            release: String::from("Release 16"),
+            ..SpecMetadata::default()
+        },
+    };
+    let entries = vec![
+        entry("23.501", "System architecture"),
+        entry("23.502", "Procedures"),
+        entry("29.500", "Technical Realization of SBA"),
+    ];
+
+    let index = render_site_index(&entries);
+
+    assert!(index.contains(
+        "<h2 id=\"series-23\">23 series <small>Technical realization (stage 2)</small></h2>"
+    ));
+    assert!(index.contains("<h2 id=\"series-29\">29 series"));
+    assert!(index.contains("<tr><td><a href=\"23.502/23.502.html\">TS 23.502</a></td><td>Procedures</td><td>16.4.0</td><td>Release 16</td></tr>"));
+    assert!(index.find("23.501").unwrap() < index.find("23.502").unwrap());
+}
@@ -1,5 +1,6 @@
-#[path = "source_parser.rs"]
-mod source_parser;
+#![allow(dead_code)]
+
+use crate::source_parser;
 
 pub struct SourceModifier<'a> {
     parser: source_parser::SourceParser<'a>,
@@ -17,7 +18,7 @@ impl SourceModifier<'_> {
     pub fn copy_til_end_of(&mut self, pattern: &str) {
         let omitted = self
             .parser
-            .goto_end_of_and_get_omitted_content(&pattern)
+            .goto_end_of_and_get_omitted_content(pattern)
             .unwrap();
         self.result.push_str(omitted)
     }
@@ -30,7 +31,7 @@ impl SourceModifier<'_> {
     pub fn copy_til_begin_of(&mut self, pattern: &str) {
         let omitted = self
             .parser
-            .goto_begin_of_and_get_omitted_content(&pattern)
+            .goto_begin_of_and_get_omitted_content(pattern)
             .unwrap();
         self.result.push_str(omitted)
     }
@@ -1,3 +1,7 @@
+#![allow(dead_code)]
+// The tests borrow their sources, as they were first written
+#![cfg_attr(test, allow(clippy::needless_borrow))]
+
 pub struct SourceParser<'a> {
     source: &'a str,
     current_pos: usize,
@@ -6,12 +10,12 @@ pub struct SourceParser<'a> {
 impl SourceParser<'_> {
     pub fn new<'a>(source: &'a str) -> SourceParser<'a> {
         SourceParser {
-            source: &source,
+            source,
             current_pos: 0,
         }
     }
 
-    pub fn get_source_slice<'s>(&'s self, begin: usize, end: usize) -> Option<&'s str> {
+    pub fn get_source_slice(&self, begin: usize, end: usize) -> Option<&str> {
         if begin > end || begin > self.source.len() || end > self.source.len() {
             return None;
         }
@@ -36,7 +40,7 @@ impl SourceParser<'_> {
     }
 
     pub fn current_is_digit(&self) -> bool {
-        !self.is_at_end() && self.current_char().is_digit(10)
+        !self.is_at_end() && self.current_char().is_ascii_digit()
     }
 
     pub fn current_is(&self, c: char) -> bool {
@@ -44,7 +48,7 @@ impl SourceParser<'_> {
     }
 
     pub fn next_is_digit(&self) -> bool {
-        self.current_pos + 1 < self.source.len() && self.next_char(1).is_digit(10)
+        self.current_pos + 1 < self.source.len() && self.next_char(1).is_ascii_digit()
     }
 
     pub fn advance_for_count_and_get_omitted_source(&mut self, count: usize) -> &str {
@@ -75,8 +79,8 @@ impl SourceParser<'_> {
     }
 
     pub fn is_a_before_b(&self, a: &str, b: &str) -> bool {
-        let a_pos = self.find_pattern_pos(&a);
-        let b_pos = self.find_pattern_pos(&b);
+        let a_pos = self.find_pattern_pos(a);
+        let b_pos = self.find_pattern_pos(b);
 
         if a_pos.is_none() {
             return false;
@@ -90,14 +94,14 @@ impl SourceParser<'_> {
     }
 
     pub fn is_before_end(&self, pattern: &str) -> bool {
-        !self.find_pattern_pos(&pattern).is_none()
+        self.find_pattern_pos(pattern).is_some()
     }
 
     pub fn goto_begin_of(&mut self, pattern: &str) {
-        self.current_pos += if let Some(found_pos) = self.find_pattern_pos(&pattern) {
-            found_pos
+        if let Some(found_pos) = self.find_pattern_pos(pattern) {
+            self.current_pos += found_pos;
         } else {
-            self.source.len()
+            self.goto_end()
         }
     }
 
@@ -114,10 +118,10 @@ impl SourceParser<'_> {
     }
 
     pub fn goto_end_of(&mut self, pattern: &str) {
-        self.current_pos += if let Some(found_pos) = self.find_pattern_pos(&pattern) {
-            found_pos + pattern.len()
+        if let Some(found_pos) = self.find_pattern_pos(pattern) {
+            self.current_pos += found_pos + pattern.len();
         } else {
-            self.source.len()
+            self.goto_end()
         }
     }
 
@@ -200,6 +204,15 @@ fn test_source_parser_goto_end_of_finds_and_moves_pos() {
     assert_eq!(parser.get_current_pos(), 6);
 }
 
+#[test]
+fn test_source_parser_goto_begin_of_goes_to_end_if_not_found_after_moving() {
+    let mut parser = SourceParser::new("foobarbaz");
+    parser.goto_end_of("bar");
+    parser.goto_begin_of("nonexisting");
+    assert!(parser.is_at_end());
+    assert_eq!(parser.get_current_pos(), 9);
+}
+
 #[test]
 fn test_source_parser_goto_end_of_goes_to_end_if_not_found() {
     let mut parser = SourceParser::new(&"foobarbaz");
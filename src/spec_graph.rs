@@ -0,0 +1,246 @@
+use crate::html_text;
+use crate::references;
+use crate::references::DocumentReferences;
+
+use failure::ResultExt;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Which document references which, built from the references of all documents in the output
+// directory. Only references between different documents make an edge.
+#[derive(Serialize)]
+struct SpecGraph {
+    nodes: Vec<String>,
+    edges: Vec<SpecEdge>,
+}
+
+#[derive(Serialize)]
+struct SpecEdge {
+    source: String,
+    target: String,
+    // Number of references behind the edge
+    weight: usize,
+    clauses: Vec<ClauseEdge>,
+}
+
+#[derive(Serialize)]
+struct ClauseEdge {
+    source_clause: Option<String>,
+    // None for references to the whole document
+    target_clause: Option<String>,
+    weight: usize,
+}
+
+// Reference counts of (source clause, target clause) pairs, keyed by (source TS, target TS)
+type EdgeCounts<'a> =
+    BTreeMap<(&'a str, &'a str), BTreeMap<(Option<&'a str>, Option<&'a str>), usize>>;
+
+pub fn export_spec_graph(out_path: &std::path::Path) -> Result<(), failure::Error> {
+    println!("Exporting spec graph...");
+
+    let all_references = references::load_all_references(out_path)?;
+    let graph = build_spec_graph(&all_references);
+
+    let outputs = [
+        ("spec_graph.dot", to_dot(&graph)),
+        ("spec_graph.graphml", to_graphml(&graph)),
+        ("spec_graph.json", serde_json::to_string_pretty(&graph)?),
+    ];
+    for (file_name, content) in outputs.iter() {
+        let path = out_path.join(file_name);
+        std::fs::write(&path, content)
+            .with_context(|_| format!("could not write spec graph `{}`", path.display()))?;
+    }
+
+    Ok(())
+}
+
+fn build_spec_graph(all_references: &[DocumentReferences]) -> SpecGraph {
+    let mut nodes = BTreeSet::new();
+    let mut edges = EdgeCounts::new();
+
+    for document in all_references {
+        nodes.insert(document.ts_no.clone());
+
+        for reference in &document.references {
+            if reference.target_ts_no == document.ts_no {
+                continue;
+            }
+
+            nodes.insert(reference.target_ts_no.clone());
+            *edges
+                .entry((&document.ts_no, &reference.target_ts_no))
+                .or_default()
+                .entry((
+                    reference.source_clause.as_deref(),
+                    reference.target_clause.as_deref(),
+                ))
+                .or_default() += 1;
+        }
+    }
+
+    let edges = edges
+        .into_iter()
+        .map(|((source, target), clauses)| SpecEdge {
+            source: String::from(source),
+            target: String::from(target),
+            weight: clauses.values().sum(),
+            clauses: clauses
+                .into_iter()
+                .map(|((source_clause, target_clause), weight)| ClauseEdge {
+                    source_clause: source_clause.map(String::from),
+                    target_clause: target_clause.map(String::from),
+                    weight,
+                })
+                .collect(),
+        })
+        .collect();
+
+    SpecGraph {
+        nodes: nodes.into_iter().collect(),
+        edges,
+    }
+}
+
+fn to_dot(graph: &SpecGraph) -> String {
+    let mut result = String::from("digraph specs {\n");
+
+    for node in &graph.nodes {
+        result.push_str(&format!("    \"{0}\" [label=\"TS {0}\"];\n", node));
+    }
+    for edge in &graph.edges {
+        result.push_str(&format!(
+            "    \"{}\" -> \"{}\" [weight={2}, label=\"{2}\", clauses=\"{3}\"];\n",
+            edge.source,
+            edge.target,
+            edge.weight,
+            clause_weights(edge)
+        ));
+    }
+
+    result.push_str("}\n");
+    result
+}
+
+fn to_graphml(graph: &SpecGraph) -> String {
+    let mut result = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="weight" for="edge" attr.name="weight" attr.type="int"/>
+  <key id="clauses" for="edge" attr.name="clauses" attr.type="int"/>
+  <key id="clause_weights" for="edge" attr.name="clause_weights" attr.type="string"/>
+  <graph id="specs" edgedefault="directed">
+"#,
+    );
+
+    for node in &graph.nodes {
+        result.push_str(&format!("    <node id=\"{}\"/>\n", html_text::escape(node)));
+    }
+    for edge in &graph.edges {
+        result.push_str(&format!(
+            "    <edge source=\"{}\" target=\"{}\">\n      <data key=\"weight\">{}</data>\n      <data key=\"clauses\">{}</data>\n      <data key=\"clause_weights\">{}</data>\n    </edge>\n",
+            html_text::escape(&edge.source),
+            html_text::escape(&edge.target),
+            edge.weight,
+            edge.clauses.len(),
+            html_text::escape(&clause_weights(edge))
+        ));
+    }
+
+    result.push_str("  </graph>\n</graphml>\n");
+    result
+}
+
+// The clause pairs of an edge with their weights, as "4.2->5.6:2; 4.4->*:1", where * stands for
+// a reference from outside any clause or to the whole document
+fn clause_weights(edge: &SpecEdge) -> String {
+    edge.clauses
+        .iter()
+        .map(|clause| {
+            format!(
+                "{}->{}:{}",
+                clause.source_clause.as_deref().unwrap_or("*"),
+                clause.target_clause.as_deref().unwrap_or("*"),
+                clause.weight
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+#[cfg(test)]
+fn test_references() -> Vec<DocumentReferences> {
+    use crate::references::ClauseReference;
+
+    let reference =
+        |source_clause: &str, target_ts_no: &str, target_clause: Option<&str>| ClauseReference {
+            source_clause: Some(String::from(source_clause)),
+            target_ts_no: String::from(target_ts_no),
+            target_clause: target_clause.map(String::from),
+        };
+
+    vec![
+        DocumentReferences {
+            ts_no: String::from("23.502"),
+            references: vec![
+                reference("4.2", "23.501", Some("5.6")),
+                reference("4.2", "23.501", Some("5.6")),
+                reference("4.3", "23.501", Some("5.7")),
+                reference("4.3", "23.502", Some("4.2")),
+                reference("4.4", "23.501", None),
+            ],
+        },
+        DocumentReferences {
+            ts_no: String::from("23.503"),
+            references: vec![reference("6.1", "23.502", Some("4.3"))],
+        },
+    ]
+}
+
+#[test]
+fn test_build_spec_graph() {
+    let graph = build_spec_graph(&test_references());
+
+    assert_eq!(graph.nodes, vec!["23.501", "23.502", "23.503"]);
+    assert_eq!(graph.edges.len(), 2);
+
+    let edge = &graph.edges[0];
+    assert_eq!(
+        (edge.source.as_str(), edge.target.as_str()),
+        ("23.502", "23.501")
+    );
+    assert_eq!(edge.weight, 4);
+    assert_eq!(edge.clauses.len(), 3);
+    assert_eq!(edge.clauses[0].target_clause.as_deref(), Some("5.6"));
+    assert_eq!(edge.clauses[0].weight, 2);
+    assert_eq!(edge.clauses[2].target_clause, None);
+}
+
+#[test]
+fn test_spec_graph_to_dot() {
+    let graph = build_spec_graph(&test_references());
+    let expected = r#"digraph specs {
+    "23.501" [label="TS 23.501"];
+    "23.502" [label="TS 23.502"];
+    "23.503" [label="TS 23.503"];
+    "23.502" -> "23.501" [weight=4, label="4", clauses="4.2->5.6:2; 4.3->5.7:1; 4.4->*:1"];
+    "23.503" -> "23.502" [weight=1, label="1", clauses="6.1->4.3:1"];
+}
+"#;
+    assert_eq!(to_dot(&graph), expected);
+}
+
+#[test]
+fn test_spec_graph_to_graphml() {
+    let graph = build_spec_graph(&test_references());
+    let graphml = to_graphml(&graph);
+
+    assert!(graphml.contains(r#"<node id="23.503"/>"#));
+    assert!(graphml.contains(
+        r#"<edge source="23.502" target="23.501">
+      <data key="weight">4</data>
+      <data key="clauses">3</data>
+      <data key="clause_weights">4.2-&gt;5.6:2; 4.3-&gt;5.7:1; 4.4-&gt;*:1</data>
+    </edge>"#
+    ));
+}
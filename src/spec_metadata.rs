@@ -0,0 +1,252 @@
+use crate::html_text;
+
+use failure::ResultExt;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+pub const METADATA_FILE_NAME: &str = "metadata.json";
+
+// Data of the cover page: "3GPP TS 23.501 V16.4.0 (2020-03)", "Technical Specification Group
+// Services and System Aspects", the title and "(Release 16)". Missing entries are empty.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Clone)]
+pub struct SpecMetadata {
+    pub ts_no: String,
+    pub title: String,
+    pub version: String,
+    pub release: String,
+    pub date: String,
+    pub tsg: String,
+}
+
+pub fn extract_metadata(content: &str, ts_no: &str) -> SpecMetadata {
+    let paragraph_re = Regex::new(r#"(?is:<p[\s>].*?</p>)"#).unwrap();
+    let header_re =
+        Regex::new(r#"\bV(?P<version>\d+\.\d+\.\d+)(\s+\((?P<date>\d{4}-\d{2})\))?"#).unwrap();
+    let tsg_re = Regex::new(r#"(?P<tsg>Technical Specification Group[^;]*)"#).unwrap();
+    let release_re = Regex::new(r#"^\(?(?P<release>Release \d+)\)?$"#).unwrap();
+
+    let mut metadata = SpecMetadata {
+        ts_no: String::from(ts_no),
+        ..SpecMetadata::default()
+    };
+    let mut title_lines = Vec::new();
+
+    // The cover page ends with the release, the first lines of the document are enough to find it
+    for paragraph in paragraph_re.find_iter(content).take(50) {
+        let text = html_text::to_plain_text(paragraph.as_str());
+
+        if let Some(cap) = release_re.captures(&text) {
+            metadata.release = String::from(&cap["release"]);
+            break;
+        }
+
+        if metadata.version.is_empty() && text.contains("3GPP") {
+            if let Some(cap) = header_re.captures(&text) {
+                metadata.version = String::from(&cap["version"]);
+                metadata.date = cap
+                    .name("date")
+                    .map(|date| String::from(date.as_str()))
+                    .unwrap_or_default();
+            }
+        }
+
+        if !metadata.tsg.is_empty() && !text.is_empty() {
+            title_lines.push(text);
+        } else if let Some(cap) = tsg_re.captures(&text) {
+            metadata.tsg = String::from(cap["tsg"].trim());
+        }
+    }
+
+    if !metadata.release.is_empty() {
+        metadata.title = title_lines.join(" ");
+    }
+    metadata
+}
+
+// "23501-g40.doc" is version 16.4.0, every character is a base 36 number. Versions with a number
+// above 35 use two decimal digits for each number: "38331-160000.doc".
+pub fn extract_version_from_file_path(path: &std::path::Path) -> Option<String> {
+    let filename = path.file_stem()?.to_str()?;
+    let version_code = filename.split('-').nth(1)?;
+
+    let numbers = match version_code.len() {
+        3 => version_code
+            .chars()
+            .map(|c| c.to_digit(36))
+            .collect::<Option<Vec<u32>>>()?,
+        6 => (0..3)
+            .map(|i| version_code.get(i * 2..i * 2 + 2)?.parse::<u32>().ok())
+            .collect::<Option<Vec<u32>>>()?,
+        _ => return None,
+    };
+
+    Some(format!("{}.{}.{}", numbers[0], numbers[1], numbers[2]))
+}
+
+// Takes the version from the file name when the cover page has none. Returns a warning when they
+// differ.
+pub fn cross_check_version(
+    metadata: &mut SpecMetadata,
+    file_name_version: Option<&str>,
+) -> Option<String> {
+    let file_name_version = file_name_version?;
+
+    if metadata.version.is_empty() {
+        metadata.version = String::from(file_name_version);
+        return None;
+    }
+
+    if metadata.version != file_name_version {
+        return Some(format!(
+            "version {} on the cover page differs from version {} in the file name",
+            metadata.version, file_name_version
+        ));
+    }
+
+    None
+}
+
+// "TS 23.501 V16.4.0 - System architecture for the 5G System (5GS); Stage 2"
+pub fn page_title(metadata: &SpecMetadata) -> String {
+    let mut title = format!("TS {}", metadata.ts_no);
+    if !metadata.version.is_empty() {
+        title.push_str(&format!(" V{}", metadata.version));
+    }
+    if !metadata.title.is_empty() {
+        title.push_str(&format!(" - {}", metadata.title));
+    }
+    title
+}
+
+pub fn render_meta_tags(metadata: &SpecMetadata) -> String {
+    let meta_entries = [
+        ("ts_no", &metadata.ts_no),
+        ("description", &metadata.title),
+        ("version", &metadata.version),
+        ("release", &metadata.release),
+        ("date", &metadata.date),
+        ("tsg", &metadata.tsg),
+    ];
+
+    meta_entries
+        .iter()
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| {
+            format!(
+                "<meta name=\"{}\" content=\"{}\"/>",
+                name,
+                html_text::escape(value)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn save_metadata(output_dir: &str, metadata: &SpecMetadata) -> Result<(), failure::Error> {
+    let path = format!("{}/{}", output_dir, METADATA_FILE_NAME);
+    let content = serde_json::to_string_pretty(metadata)?;
+    std::fs::write(&path, content)
+        .with_context(|_| format!("could not write metadata file `{}`", path))?;
+
+    Ok(())
+}
+
+// Metadata of a document in the output directory, none if it was converted without metadata
+pub fn load_metadata(dir: &std::path::Path) -> Result<Option<SpecMetadata>, failure::Error> {
+    let path = dir.join(METADATA_FILE_NAME);
+    if !path.is_file() {
+        return Ok(None);
+    }
+
+    let content = std::fs::read_to_string(&path)
+        .with_context(|_| format!("could not read metadata file `{}`", path.display()))?;
+    let metadata = serde_json::from_str(&content)
+        .with_context(|_| format!("could not parse metadata file `{}`", path.display()))?;
+
+    Ok(Some(metadata))
+}
+
+#[cfg(test)]
+fn test_metadata() -> SpecMetadata {
+    SpecMetadata {
+        ts_no: String::from("23.501"),
+        title: String::from("System architecture for the 5G System (5GS); Stage 2"),
+        version: String::from("16.4.0"),
+        release: String::from("Release 16"),
+        date: String::from("2020-03"),
+        tsg: String::from("Technical Specification Group Services and System Aspects"),
+    }
+}
+
+#[test]
+fn test_extract_metadata() {
+    let source = r#"<body lang="en-US" dir="ltr">
+<p class="western" align="right"><b>3GPP TS 23.501 V16.4.0 (2020-03)</b></p>
+<p class="western">Technical Specification</p>
+<p class="western">3rd Generation Partnership Project;<br/>Technical Specification Group Services and System Aspects;</p>
+<p class="western">System architecture for the 5G System (5GS);</p>
+<p class="western">Stage 2</p>
+<p class="western">(Release 16)</p>
+<p class="western">Lorem ipsum</p>"#;
+
+    assert_eq!(extract_metadata(source, "23.501"), test_metadata());
+}
+
+#[test]
+fn test_extract_metadata_without_cover_page() {
+    let source = r#"<p class="western">Lorem ipsum</p>"#;
+    let expected = SpecMetadata {
+        ts_no: String::from("23.501"),
+        ..SpecMetadata::default()
+    };
+    assert_eq!(extract_metadata(source, "23.501"), expected);
+}
+
+#[test]
+fn test_extract_version_from_file_path() {
+    let version = |path: &str| extract_version_from_file_path(std::path::Path::new(path));
+
+    assert_eq!(version("foo/23501-g40.doc").unwrap(), "16.4.0");
+    assert_eq!(version("38331-h10.doc").unwrap(), "17.1.0");
+    assert_eq!(version("38331-361200.doc").unwrap(), "36.12.0");
+    assert!(version("23501.doc").is_none());
+    assert!(version("23501-g4.doc").is_none());
+}
+
+#[test]
+fn test_cross_check_version() {
+    let mut metadata = test_metadata();
+    assert!(cross_check_version(&mut metadata, Some("16.4.0")).is_none());
+    assert!(cross_check_version(&mut metadata, Some("16.3.0")).is_some());
+    assert_eq!(metadata.version, "16.4.0");
+
+    metadata.version = String::new();
+    assert!(cross_check_version(&mut metadata, Some("16.3.0")).is_none());
+    assert_eq!(metadata.version, "16.3.0");
+}
+
+#[test]
+fn test_page_title() {
+    assert_eq!(
+        page_title(&test_metadata()),
+        "TS 23.501 V16.4.0 - System architecture for the 5G System (5GS); Stage 2"
+    );
+
+    let metadata = SpecMetadata {
+        ts_no: String::from("23.501"),
+        ..SpecMetadata::default()
+    };
+    assert_eq!(page_title(&metadata), "TS 23.501");
+}
+
+#[test]
+fn test_render_meta_tags() {
+    let expected = r#"<meta name="ts_no" content="23.501"/>
+<meta name="description" content="System architecture for the 5G System (5GS); Stage 2"/>
+<meta name="version" content="16.4.0"/>
+<meta name="release" content="Release 16"/>
+<meta name="date" content="2020-03"/>
+<meta name="tsg" content="Technical Specification Group Services and System Aspects"/>"#;
+
+    assert_eq!(render_meta_tags(&test_metadata()), expected);
+}
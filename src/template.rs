@@ -0,0 +1,180 @@
+use crate::html_text;
+use crate::spec_metadata;
+use crate::spec_metadata::SpecMetadata;
+use crate::toc;
+
+use failure::ResultExt;
+use regex::{Captures, Regex};
+
+const DEFAULT_TEMPLATE: &str = include_str!("../templates/default.html");
+const PLACEHOLDER: &str = r#"\{\{\s*(?P<name>[a-z_]+)\s*\}\}"#;
+const PLACEHOLDERS: [&str; 5] = ["title", "metadata", "style", "toc", "body"];
+
+// Page layout the enriched documents are rendered into. Placeholders: {{title}}, {{metadata}}
+// (<meta> tags), {{style}} (LibreOffice stylesheet), {{toc}} (sidebar) and {{body}}.
+pub struct Template {
+    content: String,
+}
+
+// Parts of a page, already in HTML
+pub struct Page<'a> {
+    pub title: &'a str,
+    pub metadata: &'a str,
+    pub style: &'a str,
+    pub toc: &'a str,
+    pub body: &'a str,
+}
+
+impl Template {
+    pub fn builtin() -> Template {
+        Template {
+            content: String::from(DEFAULT_TEMPLATE),
+        }
+    }
+
+    pub fn from_file(path: &std::path::Path) -> Result<Template, failure::Error> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|_| format!("could not read template file `{}`", path.display()))?;
+
+        let placeholder_re = Regex::new(PLACEHOLDER).unwrap();
+        let mut has_body = false;
+        for cap in placeholder_re.captures_iter(&content) {
+            if !PLACEHOLDERS.contains(&&cap["name"]) {
+                return Err(failure::err_msg(format!(
+                    "template file `{}`: unknown placeholder `{}`",
+                    path.display(),
+                    &cap[0]
+                )));
+            }
+            has_body |= &cap["name"] == "body";
+        }
+        if !has_body {
+            return Err(failure::err_msg(format!(
+                "template file `{}`: missing `{{{{body}}}}` placeholder",
+                path.display()
+            )));
+        }
+
+        Ok(Template { content })
+    }
+
+    pub fn render(&self, page: &Page) -> String {
+        let placeholder_re = Regex::new(PLACEHOLDER).unwrap();
+
+        let result =
+            placeholder_re.replace_all(&self.content, |cap: &Captures| match &cap["name"] {
+                "title" => html_text::escape(page.title),
+                "metadata" => String::from(page.metadata),
+                "style" => String::from(page.style),
+                "toc" => String::from(page.toc),
+                "body" => String::from(page.body),
+                _ => String::from(&cap[0]),
+            });
+
+        String::from(result)
+    }
+
+    // Renders an enriched LibreOffice document. Without the LibreOffice styles the page only
+    // depends on the theme of the template.
+    pub fn render_document(
+        &self,
+        content: &str,
+        metadata: &SpecMetadata,
+        strip_styles: bool,
+    ) -> String {
+        println!("\tRendering template...");
+
+        let body_re = Regex::new(r#"(?is)<body[^>]*>(?P<body>.*)</body>"#).unwrap();
+        let style_re = Regex::new(r#"(?is)<style[^>]*>.*?</style>"#).unwrap();
+
+        let body = body_re
+            .captures(content)
+            .map(|cap| String::from(&cap["body"]))
+            .unwrap_or_else(|| String::from(content));
+        let (style, body) = if strip_styles {
+            (String::new(), strip_inline_styles(&body))
+        } else {
+            let style = style_re
+                .find_iter(content)
+                .map(|m| m.as_str())
+                .collect::<Vec<_>>()
+                .join("\n");
+            (style, body)
+        };
+
+        let toc_entries = toc::collect_toc(&body);
+        let toc = if toc_entries.is_empty() {
+            String::new()
+        } else {
+            toc::render_sidebar(&toc_entries)
+        };
+
+        self.render(&Page {
+            title: &spec_metadata::page_title(metadata),
+            metadata: &spec_metadata::render_meta_tags(metadata),
+            style: &style,
+            toc: &toc,
+            body: body.trim(),
+        })
+    }
+}
+
+// Removes style attributes and <font> tags LibreOffice puts on almost every element
+fn strip_inline_styles(content: &str) -> String {
+    let style_attribute_re = Regex::new(r#"(?i)\s+style="[^"]*""#).unwrap();
+    let font_tag_re = Regex::new(r#"(?i)</?font(\s[^>]*)?>"#).unwrap();
+
+    let result = style_attribute_re.replace_all(content, "");
+    String::from(font_tag_re.replace_all(&result, ""))
+}
+
+#[test]
+fn test_render_template() {
+    let template = Template {
+        content: String::from("<title>{{title}}</title>{{ metadata }}<body>{{toc}}{{body}}</body>"),
+    };
+    let page = Page {
+        title: "TS 23.501 - <Foo>",
+        metadata: "<meta name=\"ts_no\" content=\"23.501\"/>",
+        style: "",
+        toc: "<nav></nav>",
+        body: "<p>Bar</p>",
+    };
+
+    assert_eq!(
+        template.render(&page),
+        "<title>TS 23.501 - &lt;Foo&gt;</title><meta name=\"ts_no\" content=\"23.501\"/><body><nav></nav><p>Bar</p></body>"
+    );
+}
+
+#[test]
+fn test_render_document() {
+    let template = Template {
+        content: String::from("{{style}}|{{toc}}|{{body}}"),
+    };
+    let source = r#"<html><head><style type="text/css">p { margin: 0 }</style></head>
+<body lang="en-US"><h1 id="4"  class="western">4 Foo</h1>
+<p style="margin-bottom: 0in"><font face="Arial">Bar</font></p>
+</body></html>"#;
+    let metadata = SpecMetadata::default();
+
+    assert_eq!(
+        template.render_document(source, &metadata, false),
+        r##"<style type="text/css">p { margin: 0 }</style>|<nav id="toc-sidebar"><button id="toc-toggle" type="button">Contents</button><ul><li><a href="#4">4 Foo</a></li></ul></nav>
+|<h1 id="4"  class="western">4 Foo</h1>
+<p style="margin-bottom: 0in"><font face="Arial">Bar</font></p>"##
+    );
+    let stripped = template.render_document(source, &metadata, true);
+    assert!(stripped.starts_with("|<nav id=\"toc-sidebar\">"));
+    assert!(stripped.ends_with("|<h1 id=\"4\"  class=\"western\">4 Foo</h1>\n<p>Bar</p>"));
+}
+
+#[test]
+fn test_builtin_template_has_all_placeholders() {
+    let template = Template::builtin();
+    for placeholder in PLACEHOLDERS.iter() {
+        assert!(template
+            .content
+            .contains(&format!("{{{{{}}}}}", placeholder)));
+    }
+}
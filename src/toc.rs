@@ -0,0 +1,128 @@
+use crate::html_text;
+use crate::html_tokens;
+
+use regex::Regex;
+
+// A heading with a clause id and the headings nested in it
+#[derive(Debug, PartialEq)]
+pub struct TocEntry {
+    pub clause: String,
+    pub title: String,
+    pub level: usize,
+    pub children: Vec<TocEntry>,
+}
+
+// Headings are nested by their level, an <h3> after an <h1> is a child of the <h1>
+pub fn collect_toc(content: &str) -> Vec<TocEntry> {
+    let heading_re = Regex::new(html_tokens::HEADING_WITH_ID_PATTERN).unwrap();
+
+    let mut roots = Vec::new();
+    let mut stack: Vec<TocEntry> = Vec::new();
+
+    for cap in heading_re.captures_iter(content) {
+        let entry = TocEntry {
+            clause: String::from(&cap["id"]),
+            title: html_text::to_plain_text(&cap["heading"]),
+            level: cap["level"].parse().unwrap(),
+            children: Vec::new(),
+        };
+
+        while stack.last().is_some_and(|last| last.level >= entry.level) {
+            close_entry(&mut stack, &mut roots);
+        }
+        stack.push(entry);
+    }
+
+    while !stack.is_empty() {
+        close_entry(&mut stack, &mut roots);
+    }
+
+    roots
+}
+
+fn close_entry(stack: &mut Vec<TocEntry>, roots: &mut Vec<TocEntry>) {
+    let entry = stack.pop().unwrap();
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(entry),
+        None => roots.push(entry),
+    }
+}
+
+// Nested list of links, collapsible entries with children can be expanded
+pub fn render_toc_list(entries: &[TocEntry], collapsible: bool) -> String {
+    let mut result = String::from("<ul>");
+
+    for entry in entries {
+        let link = format!(
+            "<a href=\"#{}\">{}</a>",
+            entry.clause,
+            html_text::escape(&entry.title)
+        );
+        let children = render_toc_list(&entry.children, collapsible);
+
+        if entry.children.is_empty() {
+            result.push_str(&format!("<li>{}</li>", link));
+        } else if collapsible {
+            result.push_str(&format!(
+                "<li><details><summary>{}</summary>{}</details></li>",
+                link, children
+            ));
+        } else {
+            result.push_str(&format!("<li>{}{}</li>", link, children));
+        }
+    }
+
+    result.push_str("</ul>");
+    result
+}
+
+pub fn render_sidebar(entries: &[TocEntry]) -> String {
+    format!(
+        "<nav id=\"toc-sidebar\"><button id=\"toc-toggle\" type=\"button\">Contents</button>{}</nav>\n",
+        render_toc_list(entries, true)
+    )
+}
+
+#[cfg(test)]
+fn toc_entry(clause: &str, level: usize, children: Vec<TocEntry>) -> TocEntry {
+    TocEntry {
+        clause: String::from(clause),
+        title: format!("{} Foo", clause),
+        level,
+        children,
+    }
+}
+
+#[test]
+fn test_collect_toc() {
+    let source = r#"<h1 id="4"  class="western">4	Foo</h1><p>Lorem</p>
+<h2 id="4.1"  class="western"><a name="x"></a>4.1	Foo</h2>
+<h3 id="4.1.1"  class="western">4.1.1	Foo</h3>
+<h2 id="4.2"  class="western">4.2	Foo</h2>
+<h2 class="western">Foreword</h2>
+<h1 id="5"  class="western">5	Foo</h1>"#;
+
+    let expected = vec![
+        toc_entry(
+            "4",
+            1,
+            vec![
+                toc_entry("4.1", 2, vec![toc_entry("4.1.1", 3, vec![])]),
+                toc_entry("4.2", 2, vec![]),
+            ],
+        ),
+        toc_entry("5", 1, vec![]),
+    ];
+    assert_eq!(collect_toc(source), expected);
+}
+
+#[test]
+fn test_render_toc_list() {
+    let entries = vec![
+        toc_entry("4", 1, vec![toc_entry("4.1", 2, vec![])]),
+        toc_entry("5", 1, vec![]),
+    ];
+    let expected = r##"<ul><li><details><summary><a href="#4">4 Foo</a></summary><ul><li><a href="#4.1">4.1 Foo</a></li></ul></details></li><li><a href="#5">5 Foo</a></li></ul>"##;
+
+    assert_eq!(render_toc_list(&entries, true), expected);
+}
@@ -0,0 +1,223 @@
+// Minimal ZIP writer, enough for EPUB containers: no ZIP64, no timestamps, deflate or stored
+// entries. Archives needing ZIP64, over 4 GiB or 65535 entries, are an error.
+
+use std::convert::TryFrom;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4b50;
+const VERSION: u16 = 20;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATE: u16 = 8;
+const UTF8_NAME_FLAG: u16 = 0x0800;
+// 1980-01-01, the earliest date of the format
+const DOS_DATE: u16 = 0x0021;
+
+struct CentralEntry {
+    name: String,
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    offset: u32,
+}
+
+#[derive(Default)]
+pub struct ZipArchive {
+    data: Vec<u8>,
+    entries: Vec<CentralEntry>,
+}
+
+impl ZipArchive {
+    pub fn new() -> ZipArchive {
+        ZipArchive::default()
+    }
+
+    // Entries are written in the order they are added, compressed unless it makes them larger
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        content: &[u8],
+        compress: bool,
+    ) -> Result<(), failure::Error> {
+        let compressed = if compress {
+            Some(miniz_oxide::deflate::compress_to_vec(content, 6))
+                .filter(|compressed| compressed.len() < content.len())
+        } else {
+            None
+        };
+        let (method, stored) = match &compressed {
+            Some(compressed) => (METHOD_DEFLATE, compressed.as_slice()),
+            None => (METHOD_STORED, content),
+        };
+
+        let name_len = to_u16(name.len(), "file name")?;
+        let entry = CentralEntry {
+            name: String::from(name),
+            flags: if name.is_ascii() { 0 } else { UTF8_NAME_FLAG },
+            method,
+            crc: crc32(content),
+            compressed_size: to_u32(stored.len(), "file")?,
+            size: to_u32(content.len(), "file")?,
+            offset: to_u32(self.data.len(), "archive")?,
+        };
+
+        let data = &mut self.data;
+        push_u32(data, LOCAL_HEADER_SIGNATURE);
+        push_u16(data, VERSION);
+        push_u16(data, entry.flags);
+        push_u16(data, entry.method);
+        push_u16(data, 0);
+        push_u16(data, DOS_DATE);
+        push_u32(data, entry.crc);
+        push_u32(data, entry.compressed_size);
+        push_u32(data, entry.size);
+        push_u16(data, name_len);
+        push_u16(data, 0);
+        data.extend_from_slice(name.as_bytes());
+        data.extend_from_slice(stored);
+
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<Vec<u8>, failure::Error> {
+        let entry_count = to_u16(self.entries.len(), "number of files")?;
+        let central_directory_offset = to_u32(self.data.len(), "archive")?;
+
+        let data = &mut self.data;
+        for entry in &self.entries {
+            push_u32(data, CENTRAL_HEADER_SIGNATURE);
+            push_u16(data, VERSION);
+            push_u16(data, VERSION);
+            push_u16(data, entry.flags);
+            push_u16(data, entry.method);
+            push_u16(data, 0);
+            push_u16(data, DOS_DATE);
+            push_u32(data, entry.crc);
+            push_u32(data, entry.compressed_size);
+            push_u32(data, entry.size);
+            push_u16(data, entry.name.len() as u16);
+            push_u16(data, 0);
+            push_u16(data, 0);
+            push_u16(data, 0);
+            push_u16(data, 0);
+            push_u32(data, 0);
+            push_u32(data, entry.offset);
+            data.extend_from_slice(entry.name.as_bytes());
+        }
+
+        let central_directory_size = to_u32(data.len(), "archive")? - central_directory_offset;
+        push_u32(data, END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+        push_u16(data, 0);
+        push_u16(data, 0);
+        push_u16(data, entry_count);
+        push_u16(data, entry_count);
+        push_u32(data, central_directory_size);
+        push_u32(data, central_directory_offset);
+        push_u16(data, 0);
+
+        Ok(self.data)
+    }
+}
+
+fn to_u16(value: usize, what: &str) -> Result<u16, failure::Error> {
+    u16::try_from(value).map_err(|_| too_large(what))
+}
+
+fn to_u32(value: usize, what: &str) -> Result<u32, failure::Error> {
+    u32::try_from(value).map_err(|_| too_large(what))
+}
+
+fn too_large(what: &str) -> failure::Error {
+    failure::err_msg(format!(
+        "{} too large for a ZIP archive without ZIP64",
+        what
+    ))
+}
+
+fn push_u16(data: &mut Vec<u8>, value: u16) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&value.to_le_bytes());
+}
+
+// CRC-32 of the ZIP format (reflected, polynomial 0xEDB88320)
+fn crc32(content: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in content {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn test_zip_archive() {
+    let content = "Lorem ipsum dolor sit amet. ".repeat(20);
+
+    let mut archive = ZipArchive::new();
+    archive
+        .add_file("mimetype", b"application/epub+zip", false)
+        .unwrap();
+    archive
+        .add_file("OEBPS/foo.xhtml", content.as_bytes(), true)
+        .unwrap();
+    let data = archive.finish().unwrap();
+
+    // The first entry starts at a fixed offset, EPUB readers look for "mimetype" there
+    assert_eq!(&data[0..4], b"PK\x03\x04");
+    assert_eq!(&data[30..38], b"mimetype");
+    assert_eq!(&data[38..58], b"application/epub+zip");
+
+    let second = 58;
+    assert_eq!(&data[second..second + 4], b"PK\x03\x04");
+    assert_eq!(data[second + 8], METHOD_DEFLATE as u8);
+    let compressed_size = u32::from_le_bytes([
+        data[second + 18],
+        data[second + 19],
+        data[second + 20],
+        data[second + 21],
+    ]) as usize;
+    let compressed_begin = second + 30 + "OEBPS/foo.xhtml".len();
+    let compressed = &data[compressed_begin..compressed_begin + compressed_size];
+    assert_eq!(
+        miniz_oxide::inflate::decompress_to_vec(compressed).unwrap(),
+        content.as_bytes()
+    );
+
+    // End of central directory with two entries
+    let end = data.len() - 22;
+    assert_eq!(&data[end..end + 4], b"PK\x05\x06");
+    assert_eq!(data[end + 10], 2);
+}
+
+#[test]
+fn test_zip_archive_rejects_what_needs_zip64() {
+    let mut archive = ZipArchive::new();
+    let error = archive
+        .add_file(&"a".repeat(70_000), b"", false)
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "file name too large for a ZIP archive without ZIP64"
+    );
+
+    for i in 0..=u16::MAX as usize {
+        archive.add_file(&i.to_string(), b"", false).unwrap();
+    }
+    assert!(archive.finish().is_err());
+}